@@ -1,12 +1,29 @@
+// err-derive expands to impls inside an anonymous const
+#![allow(non_local_definitions)]
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::io::Cursor;
-use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::iter;
+use std::ops::RangeInclusive;
+use std::str::{self, FromStr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use base32::Alphabet::RFC4648;
 use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Utc};
 use err_derive::Error;
-use ring::hmac;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{constant_time, hmac};
 use url::{ParseError, Url};
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, Zeroizing};
 
 // -----------------------------------------------------------------------------
 // Consts
@@ -14,16 +31,212 @@ use url::{ParseError, Url};
 
 const DEFAULT_PERIOD: u64 = 30;
 const DEFAULT_DIGITS: u8 = 6;
+const ENROLLMENT_WINDOW: u8 = 3;
+const DEFAULT_LOOK_AHEAD: u64 = 10;
+const MAX_LOOK_AHEAD: u64 = 1000;
+const MAX_WINDOW_CODES: u64 = 100_000;
+
+// 10^digits for every code length a constructor can produce.
+const MODULI: [u64; 11] = [
+    1,
+    10,
+    100,
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+];
+
+// RFC 4226 appendix D, as (6 digit, 8 digit) codes for counters 0-9.
+const RFC4226_SECRET: &[u8] = b"12345678901234567890";
+const RFC4226_VECTORS: [(&str, &str); 10] = [
+    ("755224", "84755224"),
+    ("287082", "94287082"),
+    ("359152", "37359152"),
+    ("969429", "26969429"),
+    ("338314", "40338314"),
+    ("254676", "68254676"),
+    ("287922", "18287922"),
+    ("162583", "82162583"),
+    ("399871", "73399871"),
+    ("520489", "45520489"),
+];
+
+// RFC 6238 appendix B, as (timestamp, SHA1, SHA256, SHA512) codes.
+const RFC6238_SHA1_SECRET: &[u8] = b"12345678901234567890";
+const RFC6238_SHA256_SECRET: &[u8] = b"12345678901234567890123456789012";
+const RFC6238_SHA512_SECRET: &[u8] =
+    b"1234567890123456789012345678901234567890123456789012345678901234";
+const RFC6238_VECTORS: [(u64, &str, &str, &str); 6] = [
+    (59, "94287082", "46119246", "90693936"),
+    (1111111109, "07081804", "68084774", "25091201"),
+    (1111111111, "14050471", "67062674", "99943326"),
+    (1234567890, "89005924", "91819424", "93441116"),
+    (2000000000, "69279037", "90698825", "38618901"),
+    (20000000000, "65353130", "77737706", "47863826"),
+];
+
+// RFC 3986 unreserved characters are left as-is, everything else is encoded
+// so that spaces become %20 rather than the form-encoded +.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 // -----------------------------------------------------------------------------
 // Types
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Debug, Error)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Algorithm {
+    SHA1,
+    SHA256,
+    SHA512,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [Algorithm::SHA1, Algorithm::SHA256, Algorithm::SHA512];
+
+    fn hmac_algorithm(self) -> hmac::Algorithm {
+        match self {
+            Algorithm::SHA1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Algorithm::SHA256 => hmac::HMAC_SHA256,
+            Algorithm::SHA512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::SHA1 => "SHA1",
+            Algorithm::SHA256 => "SHA256",
+            Algorithm::SHA512 => "SHA512",
+        })
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    // Accepts the variations seen in the wild: sha1, SHA-256, Sha512...
+    fn from_str(s: &str) -> Result<Algorithm, Error> {
+        match s.to_ascii_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(Algorithm::SHA1),
+            "SHA256" => Ok(Algorithm::SHA256),
+            "SHA512" => Ok(Algorithm::SHA512),
+            _ => Err(Error::InvalidAlgorithm(format!(
+                "{} is not a supported algorithm",
+                s
+            ))),
+        }
+    }
+}
+
+/// How a secret string is encoded. Base32 is what otpauth URLs use, the
+/// rest turn up in vendor exports.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Encoding {
+    Base32,
+    Base32Hex,
+    Hex,
+    Base64,
+    Raw,
+}
+
+impl Encoding {
+    fn decode(self, encoded_secret: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Base32 => decode_base32(encoded_secret),
+            Encoding::Base32Hex => decode_base32hex(encoded_secret),
+            Encoding::Hex => decode_hex(encoded_secret),
+            Encoding::Base64 => base64::decode(encoded_secret.trim())
+                .map_err(|err| Error::InvalidSecret(format!("invalid base64 secret: {}", err))),
+            Encoding::Raw => Ok(encoded_secret.as_bytes().to_vec()),
+        }
+    }
+}
+
+/// A code length, usable anywhere a plain `u8` digit count is accepted.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Digits(pub u8);
+
+impl From<Digits> for u8 {
+    fn from(digits: Digits) -> u8 {
+        digits.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OtpType {
+    Totp,
+    Hotp,
+}
+
+/// Which way an HOTP token moves its counter. A few hardware tokens count
+/// down; everything else counts up.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CounterDirection {
+    Up,
+    Down,
+}
+
+/// Clock skews are reported in seconds as positive magnitudes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EnrollmentResult {
+    Correct,
+    CorrectButClockBehind(i64),
+    CorrectButClockAhead(i64),
+    Incorrect,
+}
+
+/// The non-secret parameters of an authenticator, safe to log.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct OtpConfig {
+    pub digits: u8,
+    pub period: u64,
+    pub algorithm: Algorithm,
+    pub otp_type: OtpType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerifyResult {
+    /// `step` is the time step that matched, for audit logs.
+    Valid {
+        step_offset: i64,
+        step: u64,
+    },
+    Invalid,
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
 pub enum Error {
+    #[error(display = "invalid algorithm provided")]
+    InvalidAlgorithm(String),
+
+    #[error(display = "invalid buffer provided")]
+    InvalidBuffer(String),
+
+    #[error(display = "invalid counter provided")]
+    InvalidCounter(String),
+
     #[error(display = "invalid digits provided")]
     InvalidDigits(String),
 
+    #[error(display = "invalid look-ahead provided")]
+    InvalidLookAhead(String),
+
+    #[error(display = "invalid period provided")]
+    InvalidPeriod(String),
+
+    #[error(display = "invalid time range provided")]
+    InvalidRange(String),
+
     #[error(display = "invalid secret")]
     InvalidSecret(String),
 
@@ -35,129 +248,3526 @@ pub enum Error {
 // Types - Authenticator
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Authenticator {
+    otp_type: OtpType,
+    counter: u64,
+    direction: CounterDirection,
+    look_ahead: u64,
+    algorithm: Algorithm,
     digits: u8,
+    period: u64,
     secret: Vec<u8>,
+    key: hmac::Key,
+    issuer: Option<String>,
+    account: Option<String>,
 }
 
 impl Authenticator {
+    /// Digits aren't validated here; use `AuthenticatorBuilder` or one of
+    /// the fallible `from_*` constructors for untrusted configuration.
+    ///
+    /// # Panics
+    ///
+    /// Generating or verifying any code panics if `digits` is 0.
     pub fn new<T: Into<Vec<u8>>, L: Into<u8>>(secret: T, digits: L) -> Authenticator {
+        Authenticator::new_with_algorithm(secret, digits, Algorithm::SHA1)
+    }
+
+    /// Like `new` with a non-default algorithm.
+    ///
+    /// # Panics
+    ///
+    /// Generating or verifying any code panics if `digits` is 0.
+    pub fn new_with_algorithm<T: Into<Vec<u8>>, L: Into<u8>>(
+        secret: T,
+        digits: L,
+        algorithm: Algorithm,
+    ) -> Authenticator {
+        let secret = secret.into();
+        let key = hmac::Key::new(algorithm.hmac_algorithm(), &secret);
+        Authenticator::from_parts(secret, digits.into(), algorithm, key)
+    }
+
+    fn from_parts(
+        secret: Vec<u8>,
+        digits: u8,
+        algorithm: Algorithm,
+        key: hmac::Key,
+    ) -> Authenticator {
         Authenticator {
-            secret: secret.into(),
-            digits: digits.into(),
+            otp_type: OtpType::Totp,
+            counter: 0,
+            direction: CounterDirection::Up,
+            look_ahead: DEFAULT_LOOK_AHEAD,
+            algorithm,
+            key,
+            secret,
+            digits,
+            period: DEFAULT_PERIOD,
+            issuer: None,
+            account: None,
+        }
+    }
+
+    /// Adopts an already-built `hmac::Key` instead of deriving one, for
+    /// servers preparing many authenticators up front. `key` must have been
+    /// built from `secret`; its algorithm decides the authenticator's.
+    pub fn from_hmac_key<T: Into<Vec<u8>>, L: Into<u8>>(
+        secret: T,
+        digits: L,
+        key: hmac::Key,
+    ) -> Result<Authenticator, Error> {
+        let digits = digits.into();
+        validate_digits(digits)?;
+
+        let algorithm = Algorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.hmac_algorithm() == key.algorithm())
+            .ok_or_else(|| {
+                Error::InvalidAlgorithm(format!(
+                    "{:?} is not a supported algorithm",
+                    key.algorithm()
+                ))
+            })?;
+
+        Ok(Authenticator::from_parts(
+            secret.into(),
+            digits,
+            algorithm,
+            key,
+        ))
+    }
+
+    /// Copies the secret straight from a zeroizing buffer so the caller never
+    /// has to hold it in a plain `Vec`. The copy is wiped on drop too.
+    ///
+    /// # Panics
+    ///
+    /// Generating or verifying any code panics if `digits` is 0.
+    #[cfg(feature = "zeroize")]
+    pub fn from_zeroizing<L: Into<u8>>(secret: Zeroizing<Vec<u8>>, digits: L) -> Authenticator {
+        Authenticator::new(secret.as_slice(), digits)
+    }
+
+    /// Allows 4-10 digit codes for test fixtures and small embedded
+    /// displays. Production credentials should stick to the standard 6-9.
+    pub fn new_lenient<T: Into<Vec<u8>>, L: Into<u8>>(
+        secret: T,
+        digits: L,
+    ) -> Result<Authenticator, Error> {
+        let digits = digits.into();
+        if !(4..=10).contains(&digits) {
+            return Err(Error::InvalidDigits(format!(
+                "{} is not valid digits for code length, must be between 4-10",
+                digits
+            )));
         }
+
+        Ok(Authenticator::new(secret, digits))
     }
 
     pub fn from_base32<T: Into<String>, L: Into<u8>>(
         encoded_secret: T,
         digits: L,
     ) -> Result<Authenticator, Error> {
-        match base32::decode(RFC4648 { padding: false }, &encoded_secret.into())
-            .map(|secret| Authenticator::new(secret, digits.into()))
-        {
-            Some(a) => Ok(a),
-            None => Err(Error::InvalidSecret("invalid encoded secret".to_string())),
-        }
+        Authenticator::from_encoded_secret(&encoded_secret.into(), Encoding::Base32, digits)
+    }
+
+    pub fn from_encoded_secret<L: Into<u8>>(
+        encoded_secret: &str,
+        encoding: Encoding,
+        digits: L,
+    ) -> Result<Authenticator, Error> {
+        let digits = digits.into();
+        validate_digits(digits)?;
+        encoding
+            .decode(encoded_secret)
+            .map(|secret| Authenticator::new(secret, digits))
     }
 
     pub fn from_token_url<T: Into<String>>(token: T) -> Result<Authenticator, Error> {
-        let token_url = match Url::parse(&token.into()) {
+        Authenticator::parse_token_url(&token.into(), false, &mut Vec::new())
+    }
+
+    /// Like `from_token_url` but falls back to the defaults when `digits`,
+    /// `period` or `algorithm` can't be used, so a credential with a sloppy
+    /// exporter can still be rescued. A missing or undecodable secret is
+    /// still an error.
+    pub fn from_token_url_lenient<T: Into<String>>(token: T) -> Result<Authenticator, Error> {
+        Authenticator::parse_token_url(&token.into(), true, &mut Vec::new())
+    }
+
+    /// Like `from_token_url` but also reports parameters that were ignored,
+    /// so integrators can tell when a credential won't behave as the
+    /// exporting app intended.
+    pub fn from_token_url_verbose<T: Into<String>>(
+        token: T,
+    ) -> Result<(Authenticator, Vec<String>), Error> {
+        let mut warnings = Vec::new();
+        let authenticator = Authenticator::parse_token_url(&token.into(), false, &mut warnings)?;
+        Ok((authenticator, warnings))
+    }
+
+    fn parse_token_url(
+        token: &str,
+        lenient: bool,
+        warnings: &mut Vec<String>,
+    ) -> Result<Authenticator, Error> {
+        let token_url = match Url::parse(token) {
             Ok(u) => u,
             Err(err) => return Err(Error::InvalidTokenURL(err)),
         };
 
+        let otp_type = match token_url.host_str() {
+            Some("hotp") => OtpType::Hotp,
+            Some("totp") => OtpType::Totp,
+            other => {
+                warnings.push(format!(
+                    "unsupported type {:?}, assuming totp",
+                    other.unwrap_or("")
+                ));
+                OtpType::Totp
+            }
+        };
+
+        let (issuer, account) = parse_label(token_url.path().trim_start_matches('/'));
+        let mut issuer = issuer.filter(|issuer| !issuer.is_empty());
+        let mut algorithm = Algorithm::SHA1;
+        let mut counter: u64 = 0;
         let mut digits: u8 = DEFAULT_DIGITS;
+        let mut period: u64 = DEFAULT_PERIOD;
         let mut secret: String = "".to_string();
         for (k, v) in token_url.query_pairs() {
-            if k == "digits" {
+            if k == "algorithm" {
+                algorithm = match v.parse::<Algorithm>() {
+                    Ok(a) => a,
+                    Err(_) if lenient => {
+                        warnings.push(format!("invalid algorithm {:?}, using SHA1", v));
+                        Algorithm::SHA1
+                    }
+                    Err(err) => return Err(err),
+                };
+            } else if k == "digits" {
                 let string_digits: String = v.into_owned();
-                digits = match string_digits.parse::<u8>() {
-                    Ok(d) => d,
+                digits = match string_digits.parse::<u8>().map(|d| (d, validate_digits(d))) {
+                    Ok((d, Ok(()))) => d,
+                    _ if lenient => {
+                        warnings.push(format!(
+                            "invalid digits {:?}, using {}",
+                            string_digits, DEFAULT_DIGITS
+                        ));
+                        DEFAULT_DIGITS
+                    }
+                    Ok((_, Err(err))) => return Err(err),
                     Err(err) => return Err(Error::InvalidDigits(format!("{:?}", err))),
                 };
+            } else if k == "counter" && otp_type == OtpType::Hotp {
+                counter = parse_counter(&v)?;
+            } else if k == "period" && otp_type == OtpType::Totp {
+                period = match v.parse::<u64>().map(|p| (p, validate_period(p))) {
+                    Ok((p, Ok(()))) => p,
+                    _ if lenient => {
+                        warnings.push(format!("invalid period {:?}, using {}", v, DEFAULT_PERIOD));
+                        DEFAULT_PERIOD
+                    }
+                    Ok((_, Err(err))) => return Err(err),
+                    Err(err) => return Err(Error::InvalidPeriod(format!("{:?}", err))),
+                };
             } else if k == "secret" {
                 secret = v.into_owned();
+            } else if k == "issuer" {
+                match issuer {
+                    None => issuer = Some(v.into_owned()),
+                    Some(ref label_issuer) if *label_issuer != v => warnings.push(format!(
+                        "issuer parameter {:?} ignored in favour of label issuer {:?}",
+                        v, label_issuer
+                    )),
+                    Some(_) => {}
+                }
+            } else if k == "counter" || k == "period" {
+                warnings.push(format!("parameter {:?} ignored for this type", k));
+            } else {
+                warnings.push(format!("unsupported parameter {:?} ignored", k));
             }
         }
 
-        if digits > 9 || digits < 6 {
-            return Err(Error::InvalidDigits(format!(
-                "{} is not valid digits for code length, must be between 6-9",
-                digits
-            )));
-        }
+        validate_digits(digits)?;
 
-        if secret == "" {
+        if secret.is_empty() {
             return Err(Error::InvalidSecret("empty secret".to_string()));
         }
 
-        Authenticator::from_base32(secret, digits)
+        let secret = decode_base32(&secret)?;
+        let mut authenticator =
+            Authenticator::new_with_algorithm(secret, digits, algorithm).with_period(period)?;
+        authenticator.otp_type = otp_type;
+        authenticator.counter = counter;
+        authenticator.issuer = issuer.filter(|issuer| !issuer.is_empty());
+        authenticator.account = Some(account).filter(|account| !account.is_empty());
+        Ok(authenticator)
+    }
+
+    /// Builds an `otpauth://` URL that only carries parameters which differ
+    /// from the defaults, keeping QR codes as small as possible.
+    pub fn to_token_url(&self, label: &str) -> String {
+        let mut params = vec![("secret", self.secret_base32())];
+        if self.algorithm != Algorithm::SHA1 {
+            params.push(("algorithm", self.algorithm.to_string()));
+        }
+        if self.digits != DEFAULT_DIGITS {
+            params.push(("digits", self.digits.to_string()));
+        }
+        match self.otp_type {
+            OtpType::Totp if self.period != DEFAULT_PERIOD => {
+                params.push(("period", self.period.to_string()))
+            }
+            OtpType::Totp => {}
+            OtpType::Hotp => params.push(("counter", self.counter.to_string())),
+        }
+
+        self.build_token_url(&encode_component(label), &params)
+    }
+
+    /// Builds the form Google Authenticator expects: the issuer is repeated
+    /// as a label prefix and as a parameter, and every parameter is spelled
+    /// out even when it is the default. Some apps ignore any of these that
+    /// `to_token_url` leaves out.
+    pub fn to_google_url(&self, label: &str, issuer: &str) -> String {
+        let mut params = vec![
+            ("secret", self.secret_base32()),
+            ("issuer", issuer.to_string()),
+            ("algorithm", self.algorithm.to_string()),
+            ("digits", self.digits.to_string()),
+        ];
+        match self.otp_type {
+            OtpType::Totp => params.push(("period", self.period.to_string())),
+            OtpType::Hotp => params.push(("counter", self.counter.to_string())),
+        }
+
+        let path = format!("{}:{}", encode_component(issuer), encode_component(label));
+        self.build_token_url(&path, &params)
+    }
+
+    /// The same URL as `to_google_url` (or `to_token_url` without an issuer)
+    /// as a `Url`, for callers that want to adjust parameters before
+    /// serializing it.
+    pub fn otpauth_url(&self, label: &str, issuer: Option<&str>) -> Url {
+        let token_url = match issuer {
+            Some(issuer) => self.to_google_url(label, issuer),
+            None => self.to_token_url(label),
+        };
+        Url::parse(&token_url).expect("generated token url is always valid")
+    }
+
+    fn build_token_url(&self, path: &str, params: &[(&str, String)]) -> String {
+        let otp_type = match self.otp_type {
+            OtpType::Totp => "totp",
+            OtpType::Hotp => "hotp",
+        };
+        let query: Vec<String> = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, encode_component(v)))
+            .collect();
+        format!("otpauth://{}/{}?{}", otp_type, path, query.join("&"))
+    }
+
+    fn secret_base32(&self) -> String {
+        base32::encode(RFC4648 { padding: false }, &self.secret)
+    }
+
+    /// Sets which way HOTP verification scans from, and advances, the
+    /// stored counter. Defaults to `CounterDirection::Up`.
+    pub fn with_direction(mut self, direction: CounterDirection) -> Authenticator {
+        self.direction = direction;
+        self
+    }
+
+    /// Sets how many counters past the stored one HOTP verification scans
+    /// by default. Capped so a misconfiguration can't make each
+    /// verification arbitrarily expensive.
+    pub fn with_look_ahead(mut self, look_ahead: u64) -> Result<Authenticator, Error> {
+        check_look_ahead(look_ahead)?;
+        self.look_ahead = look_ahead;
+        Ok(self)
+    }
+
+    pub fn with_period(mut self, period: u64) -> Result<Authenticator, Error> {
+        validate_period(period)?;
+
+        self.period = period;
+        Ok(self)
+    }
+
+    /// A copy of this authenticator using `algorithm` instead, with the key
+    /// rebuilt, for probing which algorithm a server expects.
+    pub fn with_algorithm(&self, algorithm: Algorithm) -> Authenticator {
+        let mut authenticator = self.clone();
+        authenticator.key = hmac::Key::new(algorithm.hmac_algorithm(), &self.secret);
+        authenticator.algorithm = algorithm;
+        authenticator
+    }
+
+    pub fn config(&self) -> OtpConfig {
+        OtpConfig {
+            digits: self.digits,
+            period: self.period,
+            algorithm: self.algorithm,
+            otp_type: self.otp_type,
+        }
+    }
+
+    pub fn otp_type(&self) -> OtpType {
+        self.otp_type
+    }
+
+    /// The stored HOTP counter; always 0 for TOTP credentials.
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    pub fn direction(&self) -> CounterDirection {
+        self.direction
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn digits(&self) -> u8 {
+        self.digits
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// The raw key material. Treat it like a password: don't log or persist
+    /// it anywhere the base32 secret itself wouldn't be allowed.
+    pub fn secret_bytes(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// A short masked form of the base32 secret, e.g. `MZ…LU`, for telling
+    /// credentials apart in an admin UI. Secrets too short to spare four
+    /// characters are masked entirely.
+    pub fn secret_hint(&self) -> String {
+        let encoded = self.secret_base32();
+        if encoded.len() < 16 {
+            return "…".to_string();
+        }
+        format!("{}…{}", &encoded[..2], &encoded[encoded.len() - 2..])
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
     }
 
     pub fn generate_totp(&self) -> u32 {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        self.generate_hotp(timestamp / DEFAULT_PERIOD)
+        self.generate_totp_at(now())
+    }
+
+    pub fn generate_totp_at(&self, timestamp: u64) -> u32 {
+        self.generate_hotp(time_step(timestamp, self.period, 0))
+    }
+
+    pub fn generate_totp_formatted<F: CodeFormatter>(&self, formatter: &F) -> String {
+        self.generate_totp_formatted_at(formatter, now())
+    }
+
+    pub fn generate_totp_formatted_at<F: CodeFormatter>(
+        &self,
+        formatter: &F,
+        timestamp: u64,
+    ) -> String {
+        let counter = time_step(timestamp, self.period, 0);
+        let truncated = sign_truncated(&self.key, &counter.to_be_bytes(), self.digits, None);
+        formatter.format(truncated, self.digits)
+    }
+
+    /// Generates with `algorithm` instead of the configured one, leaving the
+    /// authenticator untouched. Meant for diagnostics alongside
+    /// `verify_totp_any_algorithm`.
+    pub fn generate_totp_with_algorithm(&self, algorithm: Algorithm, timestamp: u64) -> u32 {
+        let key = hmac::Key::new(algorithm.hmac_algorithm(), &self.secret);
+        hotp(&key, time_step(timestamp, self.period, 0), self.digits)
+    }
+
+    /// Returns the code along with the step that produced it, read from a
+    /// single clock sample. The step identifies the code's validity window,
+    /// e.g. as a replay protection key.
+    pub fn generate_totp_with_step(&self) -> (u64, String) {
+        self.generate_totp_with_step_at(now())
+    }
+
+    pub fn generate_totp_with_step_at(&self, timestamp: u64) -> (u64, String) {
+        let step = time_step(timestamp, self.period, 0);
+        (step, self.generate_hotp_string(step))
+    }
+
+    pub fn generate_totp_string(&self) -> String {
+        self.generate_totp_string_at(now())
+    }
+
+    pub fn generate_totp_string_at(&self, timestamp: u64) -> String {
+        pad(self.generate_totp_at(timestamp), self.digits)
+    }
+
+    /// Generates the code for `dt`, refusing times before the unix epoch
+    /// rather than letting them wrap.
+    #[cfg(feature = "chrono")]
+    pub fn generate_totp_at_datetime(&self, dt: DateTime<Utc>) -> Result<String, Error> {
+        if dt.timestamp() < 0 {
+            return Err(Error::InvalidRange(format!(
+                "{} is before the unix epoch",
+                dt
+            )));
+        }
+        Ok(self.generate_totp_string_at(dt.timestamp() as u64))
+    }
+
+    /// Yields `(step_timestamp, code)` for the step containing `timestamp`
+    /// and every step after it.
+    pub fn codes_from(&self, timestamp: u64) -> Codes<'_> {
+        Codes {
+            authenticator: self,
+            timestamp: Some(timestamp - timestamp % self.period),
+        }
+    }
+
+    /// `count` consecutive `(counter, code)` pairs from `start_counter`, e.g.
+    /// for printing a card of HOTP codes.
+    pub fn hotp_codes(&self, start_counter: u64, count: usize) -> Vec<(u64, String)> {
+        (start_counter..=u64::MAX)
+            .take(count)
+            .map(|counter| (counter, self.generate_hotp_string(counter)))
+            .collect()
+    }
+
+    /// Every `(step_timestamp, code)` for steps overlapping
+    /// `[start_ts, end_ts]`, e.g. for printing a sheet of codes. Ranges
+    /// spanning more than 100,000 steps are refused.
+    pub fn codes_for_window(
+        &self,
+        start_ts: u64,
+        end_ts: u64,
+    ) -> Result<Vec<(u64, String)>, Error> {
+        if start_ts > end_ts {
+            return Err(Error::InvalidRange(format!(
+                "start {} is after end {}",
+                start_ts, end_ts
+            )));
+        }
+
+        // Checked before adding one so a full u64 range can't overflow.
+        let steps = time_step(end_ts, self.period, 0) - time_step(start_ts, self.period, 0);
+        if steps >= MAX_WINDOW_CODES {
+            return Err(Error::InvalidRange(format!(
+                "{} codes requested, at most {} allowed",
+                steps as u128 + 1,
+                MAX_WINDOW_CODES
+            )));
+        }
+        let count = steps + 1;
+
+        Ok(self.codes_from(start_ts).take(count as usize).collect())
     }
 
+    pub fn remaining_seconds(&self) -> u64 {
+        self.remaining_seconds_at(now())
+    }
+
+    /// Seconds until the code for `timestamp` expires; a full period at an
+    /// exact boundary.
+    pub fn remaining_seconds_at(&self, timestamp: u64) -> u64 {
+        self.period - timestamp % self.period
+    }
+
+    pub fn next_boundary(&self) -> u64 {
+        self.next_boundary_at(now())
+    }
+
+    /// Unix time at which the code for `timestamp` changes; at an exact
+    /// boundary that is the following one.
+    pub fn next_boundary_at(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_add(self.remaining_seconds_at(timestamp))
+    }
+
+    pub fn refresh_in(&self) -> Duration {
+        self.refresh_in_at(now())
+    }
+
+    pub fn refresh_in_at(&self, timestamp: u64) -> Duration {
+        Duration::from_secs(self.remaining_seconds_at(timestamp))
+    }
+
+    /// # Panics
+    ///
+    /// If the authenticator was built with 0 digits, which only the
+    /// unvalidated constructors allow.
     pub fn generate_hotp(&self, counter: u64) -> u32 {
-        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.secret);
-        let tag = hmac::sign(&key, &counter.to_be_bytes());
-        let digest = tag.as_ref();
-        let offset = (digest[19] & 15) as usize;
-        let mut reader = Cursor::new(digest[offset..offset + 4].to_vec());
-        let code = reader.read_u32::<BigEndian>().unwrap() & 0x7fff_ffff;
-        code % (10u32).overflowing_pow(self.digits as u32).0
+        hotp(&self.key, counter, self.digits)
     }
-}
 
-// -----------------------------------------------------------------------------
-// Tests
-// -----------------------------------------------------------------------------
+    /// Truncates at a fixed `offset` instead of the RFC 4226 dynamic one, for
+    /// bug-for-bug compatibility with legacy systems. `None` is standard.
+    ///
+    /// # Panics
+    ///
+    /// If `offset + 4` exceeds the digest length of the algorithm.
+    pub fn generate_hotp_with_offset(&self, counter: u64, offset: Option<usize>) -> u32 {
+        hotp_with_offset(&self.key, counter, self.digits, offset)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::Authenticator;
+    /// Non-standard: RFC 4226 mandates an 8 byte counter. This left-pads or
+    /// truncates the big-endian counter to `width` bytes for systems that
+    /// got that wrong.
+    pub fn generate_hotp_counter_width(&self, counter: u64, width: usize) -> u32 {
+        let counter = counter.to_be_bytes();
+        let message = if width <= counter.len() {
+            counter[counter.len() - width..].to_vec()
+        } else {
+            let mut padded = vec![0; width - counter.len()];
+            padded.extend_from_slice(&counter);
+            padded
+        };
 
-    #[test]
-    fn from_token_url() {
-        let token_url =
-            "otpauth://totp/localhost?secret=MZQWWZLTMVRXEZLU&issuer=localhost&digits=6";
-        assert_eq!(
-            Authenticator::from_token_url(token_url)
-                .unwrap()
-                .generate_hotp(0),
-            937044
-        );
+        sign_and_truncate(&self.key, &message, self.digits, None)
+    }
 
-        assert_eq!(
-            Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6)
-                .unwrap()
-                .generate_hotp(0),
-            937044
-        );
+    /// Zero-padded to the configured digits, unlike `generate_hotp` which
+    /// drops leading zeros.
+    pub fn generate_hotp_string(&self, counter: u64) -> String {
+        pad(self.generate_hotp(counter), self.digits)
     }
 
-    #[test]
-    fn generate_hotp() {
-        assert_eq!(Authenticator::new("fakesecret", 6).generate_hotp(0), 937044);
+    /// Writes the zero-padded ASCII code for `counter` into the front of
+    /// `buf` without allocating, returning the number of bytes written.
+    pub fn write_hotp_code(&self, counter: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let digits = self.digits as usize;
+        if buf.len() < digits {
+            return Err(Error::InvalidBuffer(format!(
+                "buffer holds {} bytes but {} digits are needed",
+                buf.len(),
+                digits
+            )));
+        }
 
-        assert_eq!(
-            Authenticator::new("fakesecret", 7).generate_hotp(0),
-            1937044
-        );
+        let mut code = self.generate_hotp(counter);
+        for b in buf[..digits].iter_mut().rev() {
+            *b = b'0' + (code % 10) as u8;
+            code /= 10;
+        }
+        Ok(digits)
+    }
 
-        assert_eq!(
-            Authenticator::new("fakesecret", 8).generate_hotp(0),
-            41937044
-        );
+    /// Diagnostic only: reports which algorithm produces `code` within
+    /// `window` steps of now. Accepting any algorithm widens the set of
+    /// valid codes, so this must not be used to authenticate users.
+    pub fn verify_totp_any_algorithm(&self, code: u32, window: u8) -> Option<Algorithm> {
+        self.verify_totp_any_algorithm_at(code, window, now())
+    }
 
-        assert_eq!(
-            Authenticator::new("fakesecret", 9).generate_hotp(0),
-            741937044
-        );
+    pub fn verify_totp_any_algorithm_at(
+        &self,
+        code: u32,
+        window: u8,
+        timestamp: u64,
+    ) -> Option<Algorithm> {
+        let steps = window_steps(time_step(timestamp, self.period, 0), window);
+        Algorithm::ALL.iter().copied().find(|&algorithm| {
+            let key = hmac::Key::new(algorithm.hmac_algorithm(), &self.secret);
+            steps
+                .clone()
+                .any(|counter| hotp(&key, counter, self.digits) == code)
+        })
+    }
+
+    /// Accepts the current code or the one immediately before it, so users
+    /// who start typing just before a rollover aren't rejected. This doubles
+    /// the set of valid codes; use `verify_totp_strict` to accept only the
+    /// current one.
+    pub fn verify_totp(&self, code: u32) -> bool {
+        self.verify_totp_at(code, now())
+    }
+
+    pub fn verify_totp_at(&self, code: u32, timestamp: u64) -> bool {
+        let step = time_step(timestamp, self.period, 0);
+        (step.saturating_sub(1)..=step).any(|counter| self.generate_hotp(counter) == code)
+    }
+
+    pub fn verify_totp_strict(&self, code: u32) -> bool {
+        self.verify_totp_strict_at(code, now())
+    }
+
+    pub fn verify_totp_strict_at(&self, code: u32, timestamp: u64) -> bool {
+        self.generate_totp_at(timestamp) == code
+    }
+
+    /// Scans from the stored counter up to `look_ahead` counters ahead in
+    /// the configured direction, falling back to the configured look-ahead,
+    /// and returns the counter that matched. An explicit `look_ahead` above
+    /// `MAX_LOOK_AHEAD` never verifies.
+    pub fn verify_hotp_window(&self, code: u32, look_ahead: Option<u64>) -> Option<u64> {
+        let look_ahead = look_ahead.unwrap_or(self.look_ahead);
+        check_look_ahead(look_ahead).ok()?;
+        match self.direction {
+            CounterDirection::Up => (self.counter..=self.counter.saturating_add(look_ahead))
+                .find(|&counter| self.generate_hotp(counter) == code),
+            CounterDirection::Down => (self.counter.saturating_sub(look_ahead)..=self.counter)
+                .rev()
+                .find(|&counter| self.generate_hotp(counter) == code),
+        }
+    }
+
+    /// On a match within the configured look-ahead, moves the stored counter
+    /// past the matched one so the code can't be replayed. A code whose
+    /// counter has nowhere left to move, e.g. 0 when counting down, is
+    /// rejected since it could never be marked as used.
+    pub fn verify_and_advance(&mut self, code: u32) -> bool {
+        let next = self
+            .verify_hotp_window(code, None)
+            .and_then(|counter| match self.direction {
+                CounterDirection::Up => counter.checked_add(1),
+                CounterDirection::Down => counter.checked_sub(1),
+            });
+        match next {
+            Some(next) => {
+                self.counter = next;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Verifies a code as typed by a user: whitespace is ignored but leading
+    /// zeros are significant, so the digit count must match exactly.
+    pub fn verify_totp_str(&self, code: &str, window: u8) -> bool {
+        self.verify_totp_str_at(code, window, now())
+    }
+
+    pub fn verify_totp_str_at(&self, code: &str, window: u8, timestamp: u64) -> bool {
+        self.match_code_str(
+            window_steps(time_step(timestamp, self.period, 0), window),
+            code,
+        )
+        .is_some()
+    }
+
+    /// Migration aid for credentials whose digit count changed silently:
+    /// accepts a code that matches at any of the `candidates` lengths. This
+    /// is strictly looser than `verify_totp_str`, so use it only while a
+    /// migration is in progress. Lengths outside the standard 6-9 are never
+    /// accepted.
+    pub fn verify_totp_any_digits(&self, code_str: &str, candidates: &[u8], window: u8) -> bool {
+        self.verify_totp_any_digits_at(code_str, candidates, window, now())
+    }
+
+    pub fn verify_totp_any_digits_at(
+        &self,
+        code_str: &str,
+        candidates: &[u8],
+        window: u8,
+        timestamp: u64,
+    ) -> bool {
+        let steps = window_steps(time_step(timestamp, self.period, 0), window);
+        candidates
+            .iter()
+            .filter(|&&digits| validate_digits(digits).is_ok())
+            .fold(false, |matched, &digits| {
+                let found = self.match_code_str_digits(steps.clone(), code_str, digits);
+                matched | found.is_some()
+            })
+    }
+
+    /// Allocation-free counterpart to `verify_totp_str` for embedded use:
+    /// each candidate code is written into `buf`, which must hold at least
+    /// `digits` bytes, and compared with `code` exactly.
+    pub fn verify_totp_buf(&self, code: &[u8], window: u8, buf: &mut [u8]) -> Result<bool, Error> {
+        self.verify_totp_buf_at(code, window, now(), buf)
+    }
+
+    pub fn verify_totp_buf_at(
+        &self,
+        code: &[u8],
+        window: u8,
+        timestamp: u64,
+        buf: &mut [u8],
+    ) -> Result<bool, Error> {
+        let mut matched = false;
+        for counter in window_steps(time_step(timestamp, self.period, 0), window) {
+            let written = self.write_hotp_code(counter, buf)?;
+            matched |= codes_equal(&buf[..written], code);
+        }
+        Ok(matched)
+    }
+
+    /// Returns the canonical zero-padded code that matched, so a UI can echo
+    /// back exactly what was accepted. Unlike `verify_totp_str`, dropped
+    /// leading zeros are restored before comparing.
+    pub fn verify_totp_normalized(&self, code: &str, window: u8) -> Option<String> {
+        self.verify_totp_normalized_at(code, window, now())
+    }
+
+    pub fn verify_totp_normalized_at(
+        &self,
+        code: &str,
+        window: u8,
+        timestamp: u64,
+    ) -> Option<String> {
+        let digits: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.is_empty() {
+            return None;
+        }
+        let padded = format!("{:0>width$}", digits, width = self.digits as usize);
+        self.match_code_str(
+            window_steps(time_step(timestamp, self.period, 0), window),
+            &padded,
+        )
+        .map(|counter| self.generate_hotp_string(counter))
+    }
+
+    /// Scans up to `look_ahead` counters from `counter` in the configured
+    /// direction for a user-typed code and returns the counter that matched.
+    /// A `look_ahead` above `MAX_LOOK_AHEAD` never verifies.
+    pub fn verify_hotp_str(&self, counter: u64, code: &str, look_ahead: u64) -> Option<u64> {
+        check_look_ahead(look_ahead).ok()?;
+        match self.direction {
+            CounterDirection::Up => {
+                self.match_code_str(counter..=counter.saturating_add(look_ahead), code)
+            }
+            CounterDirection::Down => {
+                self.match_code_str((counter.saturating_sub(look_ahead)..=counter).rev(), code)
+            }
+        }
+    }
+
+    // Submitted codes are untrusted, so every candidate counter is compared
+    // and malformed input takes the same path as a wrong code: the input is
+    // copied into a buffer of the expected width and only the combined
+    // result is branched on at the end.
+    fn match_code_str<I: Iterator<Item = u64>>(&self, counters: I, code: &str) -> Option<u64> {
+        self.match_code_str_digits(counters, code, self.digits)
+    }
+
+    fn match_code_str_digits<I: Iterator<Item = u64>>(
+        &self,
+        counters: I,
+        code: &str,
+        code_digits: u8,
+    ) -> Option<u64> {
+        let digits = code_digits as usize;
+        let submitted: Vec<u8> = code.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        let well_formed = (submitted.len() == digits)
+            & submitted
+                .iter()
+                .fold(true, |digits_only, b| digits_only & b.is_ascii_digit());
+
+        let mut candidate = vec![0u8; digits];
+        for (i, c) in candidate.iter_mut().enumerate() {
+            *c = submitted.get(i).copied().unwrap_or(0);
+        }
+
+        let mut matched = None;
+        for counter in counters {
+            let expected = pad(hotp(&self.key, counter, code_digits), code_digits);
+            if codes_equal(expected.as_bytes(), &candidate) && matched.is_none() {
+                matched = Some(counter);
+            }
+        }
+
+        if well_formed {
+            matched
+        } else {
+            None
+        }
+    }
+
+    pub fn verify_totp_window(&self, code: u32, window: u8) -> bool {
+        self.verify_totp_window_at(code, window, now())
+    }
+
+    pub fn verify_totp_window_at(&self, code: u32, window: u8, timestamp: u64) -> bool {
+        self.find_step_offset(code, window, timestamp).is_some()
+    }
+
+    /// Accepts up to `behind` steps in the past and `ahead` steps in the
+    /// future, for fleets whose clocks are known to skew one way.
+    pub fn verify_totp_asymmetric(&self, code: u32, behind: u8, ahead: u8) -> bool {
+        self.verify_totp_asymmetric_at(code, behind, ahead, now())
+    }
+
+    pub fn verify_totp_asymmetric_at(
+        &self,
+        code: u32,
+        behind: u8,
+        ahead: u8,
+        timestamp: u64,
+    ) -> bool {
+        let step = time_step(timestamp, self.period, 0);
+        (step.saturating_sub(behind as u64)..=step.saturating_add(ahead as u64))
+            .any(|counter| self.generate_hotp(counter) == code)
+    }
+
+    /// Awaits `now` for a trusted timestamp (e.g. from NTP) and then verifies
+    /// synchronously; only the time lookup is asynchronous.
+    pub async fn verify_totp_async<F: Future<Output = u64>>(
+        &self,
+        code: u32,
+        window: u8,
+        now: F,
+    ) -> bool {
+        let timestamp = now.await;
+        self.verify_totp_window_at(code, window, timestamp)
+    }
+
+    /// Checks every step overlapping `[start_ts, end_ts]`, for auditing a
+    /// logged attempt against its recorded time range. A reversed range, or
+    /// one spanning more than `MAX_WINDOW_CODES` steps, never verifies.
+    pub fn verify_totp_in_range(&self, code: u32, start_ts: u64, end_ts: u64) -> bool {
+        if start_ts > end_ts {
+            return false;
+        }
+
+        let first = time_step(start_ts, self.period, 0);
+        let last = time_step(end_ts, self.period, 0);
+        if last - first >= MAX_WINDOW_CODES {
+            return false;
+        }
+
+        (first..=last).any(|step| self.generate_hotp(step) == code)
+    }
+
+    /// Every code accepted by `verify_totp_window` right now, oldest first.
+    pub fn valid_codes_now(&self, window: u8) -> Vec<String> {
+        self.valid_codes_at(window, now())
+    }
+
+    pub fn valid_codes_at(&self, window: u8, timestamp: u64) -> Vec<String> {
+        window_steps(time_step(timestamp, self.period, 0), window)
+            .map(|counter| self.generate_hotp_string(counter))
+            .collect()
+    }
+
+    /// Returns the time step that matched, so security logs can record which
+    /// window authenticated a session.
+    pub fn verify_totp_logged(&self, code: u32, window: u8) -> Option<u64> {
+        self.verify_totp_logged_at(code, window, now())
+    }
+
+    pub fn verify_totp_logged_at(&self, code: u32, window: u8, timestamp: u64) -> Option<u64> {
+        self.find_step_offset(code, window, timestamp)
+            .map(|(_, step)| step)
+    }
+
+    pub fn verify_totp_detailed(&self, code: u32, window: u8) -> VerifyResult {
+        self.verify_totp_detailed_at(code, window, now())
+    }
+
+    pub fn verify_totp_detailed_at(&self, code: u32, window: u8, timestamp: u64) -> VerifyResult {
+        match self.find_step_offset(code, window, timestamp) {
+            Some((step_offset, step)) => VerifyResult::Valid { step_offset, step },
+            None => VerifyResult::Invalid,
+        }
+    }
+
+    /// Confirms a device is in sync during enrollment: `code1` must match the
+    /// current or previous step and `code2` the step right after it.
+    pub fn verify_totp_consecutive(&self, code1: u32, code2: u32) -> bool {
+        self.verify_totp_consecutive_at(code1, code2, now())
+    }
+
+    pub fn verify_totp_consecutive_at(&self, code1: u32, code2: u32, timestamp: u64) -> bool {
+        let step = time_step(timestamp, self.period, 0);
+        [-1, 0]
+            .iter()
+            .any(|&offset| match offset_step(step, offset) {
+                Some(first) if first < u64::MAX => {
+                    self.generate_hotp(first) == code1 && self.generate_hotp(first + 1) == code2
+                }
+                _ => false,
+            })
+    }
+
+    /// Estimates how far the client clock is from ours by finding the step
+    /// within `max_window` that produced `submitted_code`. Negative values
+    /// mean the client is behind.
+    pub fn compute_offset_seconds(&self, submitted_code: u32, max_window: u8) -> Option<i64> {
+        self.compute_offset_seconds_at(submitted_code, max_window, now())
+    }
+
+    pub fn compute_offset_seconds_at(
+        &self,
+        submitted_code: u32,
+        max_window: u8,
+        timestamp: u64,
+    ) -> Option<i64> {
+        self.find_step_offset(submitted_code, max_window, timestamp)
+            .and_then(|(offset, _)| {
+                i64::try_from(self.period)
+                    .ok()
+                    .and_then(|period| offset.checked_mul(period))
+            })
+    }
+
+    /// Checks a code entered at enrollment, telling the user whether their
+    /// device clock looks skewed rather than just failing.
+    pub fn enrollment_check(&self, code: u32) -> EnrollmentResult {
+        self.enrollment_check_at(code, now())
+    }
+
+    pub fn enrollment_check_at(&self, code: u32, timestamp: u64) -> EnrollmentResult {
+        match self.compute_offset_seconds_at(code, ENROLLMENT_WINDOW, timestamp) {
+            Some(0) => EnrollmentResult::Correct,
+            Some(offset) if offset < 0 => EnrollmentResult::CorrectButClockBehind(-offset),
+            Some(offset) => EnrollmentResult::CorrectButClockAhead(offset),
+            None => EnrollmentResult::Incorrect,
+        }
+    }
+
+    /// Snapshot of the key and parameters for hot verification paths, so
+    /// the authenticator itself needn't be cloned or shared.
+    pub fn verify_context(&self) -> VerifyContext {
+        VerifyContext {
+            key: self.key.clone(),
+            digits: self.digits,
+            period: self.period,
+        }
+    }
+
+    fn find_step_offset(&self, code: u32, window: u8, timestamp: u64) -> Option<(i64, u64)> {
+        find_step_offset(&self.key, self.digits, self.period, code, window, timestamp)
+    }
+}
+
+impl Authenticator {
+    /// Like `Debug` but includes the raw secret; never log the result.
+    pub fn debug_with_secret(&self) -> String {
+        struct WithSecret<'a>(&'a Authenticator);
+
+        impl<'a> fmt::Debug for WithSecret<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt_fields(f, &self.0.secret)
+            }
+        }
+
+        format!("{:?}", WithSecret(self))
+    }
+
+    fn fmt_fields(&self, f: &mut fmt::Formatter, secret: &dyn fmt::Debug) -> fmt::Result {
+        f.debug_struct("Authenticator")
+            .field("otp_type", &self.otp_type)
+            .field("counter", &self.counter)
+            .field("direction", &self.direction)
+            .field("look_ahead", &self.look_ahead)
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("secret", secret)
+            .field("issuer", &self.issuer)
+            .field("account", &self.account)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_fields(f, &"<redacted>")
+    }
+}
+
+// The cached hmac::Key is opaque so only our own copy of the secret can be
+// wiped here.
+#[cfg(feature = "zeroize")]
+impl Drop for Authenticator {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl PartialEq for Authenticator {
+    fn eq(&self, other: &Authenticator) -> bool {
+        let secrets_equal =
+            constant_time::verify_slices_are_equal(&self.secret, &other.secret).is_ok();
+        secrets_equal
+            && self.otp_type == other.otp_type
+            && self.counter == other.counter
+            && self.direction == other.direction
+            && self.look_ahead == other.look_ahead
+            && self.algorithm == other.algorithm
+            && self.digits == other.digits
+            && self.period == other.period
+            && self.issuer == other.issuer
+            && self.account == other.account
+    }
+}
+
+impl Eq for Authenticator {}
+
+// Hashing the secret is fine here: the hash only drives in-process lookups
+// and is never exposed where an attacker could observe it.
+impl Hash for Authenticator {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.otp_type.hash(state);
+        self.counter.hash(state);
+        self.direction.hash(state);
+        self.look_ahead.hash(state);
+        self.algorithm.hash(state);
+        self.digits.hash(state);
+        self.period.hash(state);
+        self.secret.hash(state);
+        self.issuer.hash(state);
+        self.account.hash(state);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - CodeFormatter
+// -----------------------------------------------------------------------------
+
+/// Turns the 31-bit value from dynamic truncation into display text. The
+/// default is the standard zero-padded decimal code.
+pub trait CodeFormatter {
+    fn format(&self, truncated: u32, digits: u8) -> String {
+        pad(modulo(truncated, digits), digits)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainFormatter;
+
+impl CodeFormatter for PlainFormatter {}
+
+/// Splits the decimal code into groups, e.g. `123 456`.
+#[derive(Clone, Copy, Debug)]
+pub struct GroupedFormatter {
+    pub group_size: usize,
+    pub separator: char,
+}
+
+impl Default for GroupedFormatter {
+    fn default() -> GroupedFormatter {
+        GroupedFormatter {
+            group_size: 3,
+            separator: ' ',
+        }
+    }
+}
+
+impl CodeFormatter for GroupedFormatter {
+    fn format(&self, truncated: u32, digits: u8) -> String {
+        let code = PlainFormatter.format(truncated, digits);
+        let mut grouped = String::with_capacity(code.len() * 2);
+        for (i, c) in code.chars().enumerate() {
+            if i > 0 && self.group_size > 0 && i % self.group_size == 0 {
+                grouped.push(self.separator);
+            }
+            grouped.push(c);
+        }
+        grouped
+    }
+}
+
+/// Steam Guard's five character codes; `digits` is ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SteamFormatter;
+
+impl CodeFormatter for SteamFormatter {
+    fn format(&self, truncated: u32, _digits: u8) -> String {
+        const ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+        let mut code = truncated;
+        (0..5)
+            .map(|_| {
+                let c = ALPHABET[(code % ALPHABET.len() as u32) as usize] as char;
+                code /= ALPHABET.len() as u32;
+                c
+            })
+            .collect()
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - VerifyContext
+// -----------------------------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct VerifyContext {
+    key: hmac::Key,
+    digits: u8,
+    period: u64,
+}
+
+impl VerifyContext {
+    pub fn verify(&self, code: u32, window: u8, now: u64) -> bool {
+        self.verify_detailed(code, window, now) != VerifyResult::Invalid
+    }
+
+    pub fn verify_detailed(&self, code: u32, window: u8, now: u64) -> VerifyResult {
+        match find_step_offset(&self.key, self.digits, self.period, code, window, now) {
+            Some((step_offset, step)) => VerifyResult::Valid { step_offset, step },
+            None => VerifyResult::Invalid,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - StreamingVerifier
+// -----------------------------------------------------------------------------
+
+/// Verifies a TOTP code fed one character at a time, e.g. from a TUI. Only
+/// ASCII digits are collected; once enough have arrived the result is
+/// fixed and further input is ignored.
+#[derive(Clone, Debug)]
+pub struct StreamingVerifier<'a> {
+    authenticator: &'a Authenticator,
+    window: u8,
+    timestamp: Option<u64>,
+    code: String,
+    result: Option<bool>,
+}
+
+impl<'a> StreamingVerifier<'a> {
+    /// Checks against the wall clock at the moment the last digit arrives.
+    pub fn new(authenticator: &'a Authenticator, window: u8) -> StreamingVerifier<'a> {
+        StreamingVerifier {
+            authenticator,
+            window,
+            timestamp: None,
+            code: String::with_capacity(authenticator.digits as usize),
+            result: None,
+        }
+    }
+
+    /// Checks against `timestamp` instead of the wall clock.
+    pub fn new_at(
+        authenticator: &'a Authenticator,
+        window: u8,
+        timestamp: u64,
+    ) -> StreamingVerifier<'a> {
+        StreamingVerifier {
+            timestamp: Some(timestamp),
+            ..StreamingVerifier::new(authenticator, window)
+        }
+    }
+
+    /// Returns `Some(valid)` once the expected number of digits is present.
+    pub fn push(&mut self, c: char) -> Option<bool> {
+        if self.result.is_none() && c.is_ascii_digit() {
+            self.code.push(c);
+            if self.code.len() == self.authenticator.digits as usize {
+                let timestamp = self.timestamp.unwrap_or_else(now);
+                self.result = Some(self.authenticator.verify_totp_str_at(
+                    &self.code,
+                    self.window,
+                    timestamp,
+                ));
+            }
+        }
+        self.result
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - AuthenticatorBuilder
+// -----------------------------------------------------------------------------
+
+/// Defaults to the standard SHA1, 6 digit, 30 second configuration. No
+/// `Default` is provided for `Authenticator` itself since it needs a secret.
+#[derive(Clone)]
+pub struct AuthenticatorBuilder {
+    algorithm: Algorithm,
+    digits: u8,
+    look_ahead: u64,
+    period: u64,
+    secret: Option<Vec<u8>>,
+}
+
+impl Default for AuthenticatorBuilder {
+    fn default() -> AuthenticatorBuilder {
+        AuthenticatorBuilder {
+            algorithm: Algorithm::SHA1,
+            digits: DEFAULT_DIGITS,
+            look_ahead: DEFAULT_LOOK_AHEAD,
+            period: DEFAULT_PERIOD,
+            secret: None,
+        }
+    }
+}
+
+impl AuthenticatorBuilder {
+    pub fn new() -> AuthenticatorBuilder {
+        AuthenticatorBuilder::default()
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> AuthenticatorBuilder {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn digits(mut self, digits: u8) -> AuthenticatorBuilder {
+        self.digits = digits;
+        self
+    }
+
+    pub fn look_ahead(mut self, look_ahead: u64) -> AuthenticatorBuilder {
+        self.look_ahead = look_ahead;
+        self
+    }
+
+    pub fn period(mut self, period: u64) -> AuthenticatorBuilder {
+        self.period = period;
+        self
+    }
+
+    pub fn secret<T: Into<Vec<u8>>>(mut self, secret: T) -> AuthenticatorBuilder {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    pub fn secret_base32(mut self, encoded_secret: &str) -> Result<AuthenticatorBuilder, Error> {
+        self.secret = Some(decode_base32(encoded_secret)?);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<Authenticator, Error> {
+        validate_digits(self.digits)?;
+
+        let secret = match self.secret {
+            Some(secret) if !secret.is_empty() => secret,
+            _ => return Err(Error::InvalidSecret("empty secret".to_string())),
+        };
+
+        Authenticator::new_with_algorithm(secret, self.digits, self.algorithm)
+            .with_period(self.period)?
+            .with_look_ahead(self.look_ahead)
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - AuthenticatorSet
+// -----------------------------------------------------------------------------
+
+/// How `AuthenticatorSet::merge` resolves a label present in both sets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergeStrategy {
+    KeepExisting,
+    PreferIncoming,
+    /// Keeps the existing entry and adds the incoming one as `label (2)`,
+    /// `label (3)` and so on.
+    KeepBoth,
+}
+
+/// A collection of authenticators keyed by label, kept in label order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthenticatorSet {
+    authenticators: BTreeMap<String, Authenticator>,
+}
+
+impl AuthenticatorSet {
+    pub fn new() -> AuthenticatorSet {
+        AuthenticatorSet::default()
+    }
+
+    pub fn insert<T: Into<String>>(
+        &mut self,
+        label: T,
+        authenticator: Authenticator,
+    ) -> Option<Authenticator> {
+        self.authenticators.insert(label.into(), authenticator)
+    }
+
+    pub fn get(&self, label: &str) -> Option<&Authenticator> {
+        self.authenticators.get(label)
+    }
+
+    pub fn get_mut(&mut self, label: &str) -> Option<&mut Authenticator> {
+        self.authenticators.get_mut(label)
+    }
+
+    pub fn remove(&mut self, label: &str) -> Option<Authenticator> {
+        self.authenticators.remove(label)
+    }
+
+    pub fn len(&self) -> usize {
+        self.authenticators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authenticators.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, Authenticator> {
+        self.authenticators.iter()
+    }
+
+    /// Pairs of entries sharing a secret, e.g. one credential enrolled under
+    /// two labels. Each unordered pair is reported once, in label order.
+    pub fn duplicates(&self) -> Vec<(&Authenticator, &Authenticator)> {
+        let authenticators: Vec<&Authenticator> = self.authenticators.values().collect();
+        let mut duplicates = Vec::new();
+        for (i, first) in authenticators.iter().enumerate() {
+            for second in &authenticators[i + 1..] {
+                if constant_time::verify_slices_are_equal(&first.secret, &second.secret).is_ok() {
+                    duplicates.push((*first, *second));
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// Exports every entry as a Google Authenticator
+    /// `otpauth-migration://offline` URL. The migration format only knows 6
+    /// or 8 digit codes and 30 second TOTP periods, so anything else is rejected
+    /// rather than producing a payload that won't import.
+    pub fn to_migration_url(&self) -> Result<String, Error> {
+        let mut payload = Vec::new();
+        for (label, authenticator) in self {
+            let digits = match authenticator.digits {
+                6 => 1,
+                8 => 2,
+                other => {
+                    return Err(Error::InvalidDigits(format!(
+                        "{:?} uses {} digits, the migration format only supports 6 or 8",
+                        label, other
+                    )))
+                }
+            };
+            if authenticator.otp_type == OtpType::Totp && authenticator.period != DEFAULT_PERIOD {
+                return Err(Error::InvalidPeriod(format!(
+                    "{:?} uses a {} second period, the migration format only supports {}",
+                    label, authenticator.period, DEFAULT_PERIOD
+                )));
+            }
+            let algorithm = match authenticator.algorithm {
+                Algorithm::SHA1 => 1,
+                Algorithm::SHA256 => 2,
+                Algorithm::SHA512 => 3,
+            };
+
+            let mut parameters = Vec::new();
+            write_proto_bytes(&mut parameters, 1, &authenticator.secret);
+            write_proto_bytes(&mut parameters, 2, label.as_bytes());
+            if let Some(issuer) = &authenticator.issuer {
+                write_proto_bytes(&mut parameters, 3, issuer.as_bytes());
+            }
+            write_proto_varint(&mut parameters, 4, algorithm);
+            write_proto_varint(&mut parameters, 5, digits);
+            match authenticator.otp_type {
+                OtpType::Hotp => {
+                    write_proto_varint(&mut parameters, 6, 1);
+                    write_proto_varint(&mut parameters, 7, authenticator.counter);
+                }
+                OtpType::Totp => write_proto_varint(&mut parameters, 6, 2),
+            }
+            write_proto_bytes(&mut payload, 1, &parameters);
+        }
+        write_proto_varint(&mut payload, 2, 1);
+        write_proto_varint(&mut payload, 3, 1);
+
+        Ok(format!(
+            "otpauth-migration://offline?data={}",
+            encode_component(&base64::encode(&payload))
+        ))
+    }
+
+    /// Merges `other` into this set, e.g. when restoring a backup. Whatever
+    /// the strategy, an HOTP credential present under the same label in both
+    /// ends up with the more advanced of the two counters (the lower one for
+    /// `CounterDirection::Down`) so old codes can't be replayed. Entries
+    /// whose directions differ are left as they are.
+    pub fn merge(&mut self, other: AuthenticatorSet, strategy: MergeStrategy) {
+        for (label, mut incoming) in other {
+            let existing = match self.authenticators.get_mut(&label) {
+                Some(existing) => existing,
+                None => {
+                    self.authenticators.insert(label, incoming);
+                    continue;
+                }
+            };
+
+            if existing.otp_type == OtpType::Hotp
+                && incoming.otp_type == OtpType::Hotp
+                && existing.direction == incoming.direction
+                && constant_time::verify_slices_are_equal(&existing.secret, &incoming.secret)
+                    .is_ok()
+            {
+                let counter = match existing.direction {
+                    CounterDirection::Up => existing.counter.max(incoming.counter),
+                    CounterDirection::Down => existing.counter.min(incoming.counter),
+                };
+                existing.counter = counter;
+                incoming.counter = counter;
+            }
+
+            match strategy {
+                MergeStrategy::KeepExisting => {}
+                MergeStrategy::PreferIncoming => *existing = incoming,
+                MergeStrategy::KeepBoth => {
+                    let relabeled = (2..)
+                        .map(|n| format!("{} ({})", label, n))
+                        .find(|candidate| !self.authenticators.contains_key(candidate))
+                        .unwrap();
+                    self.authenticators.insert(relabeled, incoming);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a AuthenticatorSet {
+    type Item = (&'a String, &'a Authenticator);
+    type IntoIter = btree_map::Iter<'a, String, Authenticator>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.authenticators.iter()
+    }
+}
+
+impl IntoIterator for AuthenticatorSet {
+    type Item = (String, Authenticator);
+    type IntoIter = btree_map::IntoIter<String, Authenticator>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.authenticators.into_iter()
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Types - Codes
+// -----------------------------------------------------------------------------
+
+pub struct Codes<'a> {
+    authenticator: &'a Authenticator,
+    timestamp: Option<u64>,
+}
+
+impl<'a> Iterator for Codes<'a> {
+    type Item = (u64, String);
+
+    fn next(&mut self) -> Option<(u64, String)> {
+        let timestamp = self.timestamp?;
+        self.timestamp = timestamp.checked_add(self.authenticator.period);
+        Some((
+            timestamp,
+            self.authenticator.generate_totp_string_at(timestamp),
+        ))
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Splits an otpauth label into `(issuer, account)`. The separator may be a
+/// literal or percent-encoded (`%3A`) colon, and both parts are decoded.
+pub fn parse_label(label: &str) -> (Option<String>, String) {
+    let decode = |part: &str| percent_decode_str(part).decode_utf8_lossy().into_owned();
+
+    let colon = label.find(':').map(|i| (i, i + 1));
+    let encoded = label.to_ascii_uppercase().find("%3A").map(|i| (i, i + 3));
+    let (end, start) = match (colon, encoded) {
+        (Some(c), Some(e)) => c.min(e),
+        (Some(separator), None) | (None, Some(separator)) => separator,
+        (None, None) => return (None, decode(label)),
+    };
+
+    (
+        Some(decode(&label[..end])),
+        decode(&label[start..]).trim_start().to_string(),
+    )
+}
+
+/// Checks this build against the RFC 4226 and RFC 6238 test vectors, e.g. as
+/// a startup sanity check.
+pub fn self_test() -> Result<(), String> {
+    let key = hmac::Key::new(Algorithm::SHA1.hmac_algorithm(), RFC4226_SECRET);
+    for (counter, &(six_digits, eight_digits)) in RFC4226_VECTORS.iter().enumerate() {
+        for &(digits, expected) in &[(6, six_digits), (8, eight_digits)] {
+            let code = pad(hotp(&key, counter as u64, digits), digits);
+            if code != expected {
+                return Err(format!(
+                    "RFC 4226 HOTP counter {} ({} digits): expected {}, got {}",
+                    counter, digits, expected, code
+                ));
+            }
+        }
+    }
+
+    let secrets = [
+        (Algorithm::SHA1, RFC6238_SHA1_SECRET),
+        (Algorithm::SHA256, RFC6238_SHA256_SECRET),
+        (Algorithm::SHA512, RFC6238_SHA512_SECRET),
+    ];
+    for &(timestamp, sha1, sha256, sha512) in RFC6238_VECTORS.iter() {
+        for (&(algorithm, secret), &expected) in secrets.iter().zip(&[sha1, sha256, sha512]) {
+            let code = totp(secret, timestamp, DEFAULT_PERIOD, 8, algorithm)
+                .map_err(|err| format!("RFC 6238 {} at {}: {:?}", algorithm, timestamp, err))?;
+            if code != expected {
+                return Err(format!(
+                    "RFC 6238 {} at {}: expected {}, got {}",
+                    algorithm, timestamp, expected, code
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A random `len` byte secret, base32 encoded for display at enrollment.
+/// Panics if the system random number generator fails.
+pub fn generate_secret_base32(len: usize) -> String {
+    let rng = SystemRandom::new();
+    generate_secret_base32_with(len, |buf| {
+        rng.fill(buf)
+            .expect("system random number generator failed")
+    })
+}
+
+/// Like `generate_secret_base32` but with the secret bytes supplied by
+/// `fill`, so tests can make enrollment deterministic.
+pub fn generate_secret_base32_with<F: FnMut(&mut [u8])>(len: usize, mut fill: F) -> String {
+    let mut secret = vec![0u8; len];
+    fill(&mut secret);
+    base32::encode(RFC4648 { padding: false }, &secret)
+}
+
+/// The RFC 6238 time step `(timestamp - t0) / period`; timestamps before
+/// `t0` fall into step 0.
+///
+/// # Panics
+///
+/// If `period` is 0; check untrusted values with `validate_period` first.
+pub fn time_step(timestamp: u64, period: u64, t0: u64) -> u64 {
+    debug_assert!(period > 0, "time step period must be greater than 0");
+    timestamp.saturating_sub(t0) / period
+}
+
+/// Checks a code length is in the standard 6-9 range, e.g. for validating
+/// user input before constructing an authenticator.
+pub fn validate_digits(digits: u8) -> Result<(), Error> {
+    if !(6..=9).contains(&digits) {
+        return Err(Error::InvalidDigits(format!(
+            "{} is not valid digits for code length, must be between 6-9",
+            digits
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a TOTP period is usable, i.e. non-zero.
+pub fn validate_period(period: u64) -> Result<(), Error> {
+    if period == 0 {
+        return Err(Error::InvalidPeriod(
+            "period must be greater than 0".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn totp(
+    secret: &[u8],
+    timestamp: u64,
+    period: u64,
+    digits: u8,
+    algorithm: Algorithm,
+) -> Result<String, Error> {
+    validate_digits(digits)?;
+    validate_period(period)?;
+
+    if secret.is_empty() {
+        return Err(Error::InvalidSecret("empty secret".to_string()));
+    }
+
+    let key = hmac::Key::new(algorithm.hmac_algorithm(), secret);
+    let code = hotp(&key, time_step(timestamp, period, 0), digits);
+    Ok(pad(code, digits))
+}
+
+// Counters are decimal, possibly zero-padded, but some exporters write them
+// as 0x-prefixed hex.
+fn parse_counter(counter: &str) -> Result<u64, Error> {
+    let parsed = match counter
+        .strip_prefix("0x")
+        .or_else(|| counter.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => counter.parse::<u64>(),
+    };
+
+    parsed.map_err(|err| Error::InvalidCounter(format!("{}: {}", counter, err)))
+}
+
+fn codes_equal(expected: &[u8], submitted: &[u8]) -> bool {
+    constant_time::verify_slices_are_equal(expected, submitted).is_ok()
+}
+
+fn encode_component(component: &str) -> String {
+    utf8_percent_encode(component, URL_COMPONENT).to_string()
+}
+
+// Just enough protobuf wire format for the migration payload: varint
+// (wire type 0) and length-delimited (wire type 2) fields.
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_proto_varint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+fn write_proto_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, field << 3 | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+// Whitespace and trailing padding are dropped and case is ignored, so that
+// secrets pasted in grouped or lowercase form still decode.
+fn decode_base32(encoded_secret: &str) -> Result<Vec<u8>, Error> {
+    let normalized: String = encoded_secret
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let normalized = normalized.trim_end_matches('=');
+
+    if let Some((position, c)) = normalized
+        .chars()
+        .enumerate()
+        .find(|(_, c)| !matches!(c, 'A'..='Z' | '2'..='7'))
+    {
+        return Err(Error::InvalidSecret(format!(
+            "invalid character '{}' at position {}",
+            c, position
+        )));
+    }
+
+    match base32::decode(RFC4648 { padding: false }, normalized) {
+        Some(secret) => Ok(secret),
+        None => Err(Error::InvalidSecret("invalid encoded secret".to_string())),
+    }
+}
+
+// Scans outwards from the current step so the closest match wins.
+fn find_step_offset(
+    key: &hmac::Key,
+    digits: u8,
+    period: u64,
+    code: u32,
+    window: u8,
+    timestamp: u64,
+) -> Option<(i64, u64)> {
+    let step = time_step(timestamp, period, 0);
+    iter::once(0)
+        .chain(
+            (1..=window as i64)
+                .flat_map(|distance| iter::once(-distance).chain(iter::once(distance))),
+        )
+        .filter_map(|offset| offset_step(step, offset).map(|counter| (offset, counter)))
+        .find(|&(_, counter)| hotp(key, counter, digits) == code)
+}
+
+fn window_steps(step: u64, window: u8) -> RangeInclusive<u64> {
+    step.saturating_sub(window as u64)..=step.saturating_add(window as u64)
+}
+
+// RFC 4648 section 7 "extended hex" alphabet, mapped onto the standard
+// alphabet so the base32 crate can do the decoding.
+fn decode_base32hex(encoded_secret: &str) -> Result<Vec<u8>, Error> {
+    const HEX_ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUV";
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut translated = String::with_capacity(encoded_secret.len());
+    for (position, c) in encoded_secret.chars().enumerate() {
+        if c.is_whitespace() || c == '=' {
+            translated.push(c);
+            continue;
+        }
+        match HEX_ALPHABET.find(c.to_ascii_uppercase()) {
+            Some(index) => translated.push(ALPHABET[index] as char),
+            None => {
+                return Err(Error::InvalidSecret(format!(
+                    "invalid character '{}' at position {}",
+                    c, position
+                )))
+            }
+        }
+    }
+
+    decode_base32(&translated)
+}
+
+fn decode_hex(encoded_secret: &str) -> Result<Vec<u8>, Error> {
+    let normalized: String = encoded_secret
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if !normalized.len().is_multiple_of(2) || !normalized.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidSecret("invalid hex secret".to_string()));
+    }
+
+    (0..normalized.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&normalized[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|err| Error::InvalidSecret(format!("invalid hex secret: {}", err)))
+}
+
+fn offset_step(step: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        step.checked_sub(offset.unsigned_abs())
+    } else {
+        step.checked_add(offset as u64)
+    }
+}
+
+fn check_look_ahead(look_ahead: u64) -> Result<(), Error> {
+    if look_ahead > MAX_LOOK_AHEAD {
+        return Err(Error::InvalidLookAhead(format!(
+            "{} is too large, must be at most {}",
+            look_ahead, MAX_LOOK_AHEAD
+        )));
+    }
+    Ok(())
+}
+
+fn pad(code: u32, digits: u8) -> String {
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+fn hotp(key: &hmac::Key, counter: u64, digits: u8) -> u32 {
+    hotp_with_offset(key, counter, digits, None)
+}
+
+fn hotp_with_offset(key: &hmac::Key, counter: u64, digits: u8, offset: Option<usize>) -> u32 {
+    sign_and_truncate(key, &counter.to_be_bytes(), digits, offset)
+}
+
+fn sign_and_truncate(key: &hmac::Key, message: &[u8], digits: u8, offset: Option<usize>) -> u32 {
+    modulo(sign_truncated(key, message, digits, offset), digits)
+}
+
+// The 31-bit value before reduction, for formatters that map it onto their
+// own alphabet.
+fn sign_truncated(key: &hmac::Key, message: &[u8], digits: u8, offset: Option<usize>) -> u32 {
+    // With zero digits every code would be 0 and always verify, so refuse
+    // outright in case a constructor ever stops validating.
+    assert!(digits > 0, "refusing to generate a code with zero digits");
+
+    let tag = hmac::sign(key, message);
+    dynamic_truncate(tag.as_ref(), offset)
+}
+
+fn modulo(truncated: u32, digits: u8) -> u32 {
+    (truncated as u64 % modulus(digits)) as u32
+}
+
+// Anything past the table already exceeds a 31-bit truncated value, so the
+// code is left as is.
+fn modulus(digits: u8) -> u64 {
+    MODULI.get(digits as usize).copied().unwrap_or(u64::MAX)
+}
+
+// RFC 4226 section 5.3 takes the offset from the low nibble of the last
+// byte; a fixed override only exists for non-compliant legacy systems.
+fn dynamic_truncate(digest: &[u8], offset: Option<usize>) -> u32 {
+    let offset = offset.unwrap_or((digest[digest.len() - 1] & 15) as usize);
+    let mut reader = Cursor::new(&digest[offset..offset + 4]);
+    reader.read_u32::<BigEndian>().unwrap() & 0x7fff_ffff
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::future::{self, Future};
+    use std::task::{Context, Poll, Waker};
+    use std::time::Duration;
+
+    use percent_encoding::percent_decode_str;
+    use ring::hmac;
+
+    use super::{
+        Algorithm, Authenticator, AuthenticatorBuilder, AuthenticatorSet, CodeFormatter,
+        CounterDirection, Digits, Encoding, EnrollmentResult, Error, GroupedFormatter,
+        MergeStrategy, OtpConfig, OtpType, PlainFormatter, SteamFormatter, StreamingVerifier,
+        VerifyResult, DEFAULT_DIGITS, DEFAULT_PERIOD, RFC4226_SECRET, RFC4226_VECTORS,
+        RFC6238_SHA1_SECRET, RFC6238_SHA256_SECRET, RFC6238_SHA512_SECRET, RFC6238_VECTORS,
+    };
+
+    #[test]
+    fn from_token_url() {
+        let token_url =
+            "otpauth://totp/localhost?secret=MZQWWZLTMVRXEZLU&issuer=localhost&digits=6";
+        assert_eq!(
+            Authenticator::from_token_url(token_url)
+                .unwrap()
+                .generate_hotp(0),
+            937044
+        );
+
+        assert_eq!(
+            Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6)
+                .unwrap()
+                .generate_hotp(0),
+            937044
+        );
+    }
+
+    #[test]
+    fn from_token_url_hotp() {
+        let token_url =
+            "otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8&counter=3";
+        let authenticator = Authenticator::from_token_url(token_url).unwrap();
+        assert_eq!(authenticator.otp_type(), OtpType::Hotp);
+        assert_eq!(authenticator.digits(), 8);
+        assert_eq!(authenticator.counter(), 3);
+        assert_eq!(
+            authenticator.generate_hotp_string(authenticator.counter()),
+            RFC4226_VECTORS[3].1
+        );
+
+        let token_url = "otpauth://hotp/alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=10";
+        assert!(matches!(
+            Authenticator::from_token_url(token_url),
+            Err(Error::InvalidDigits(_))
+        ));
+
+        let token_url = "otpauth://hotp/alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=x";
+        assert!(matches!(
+            Authenticator::from_token_url(token_url),
+            Err(Error::InvalidCounter(_))
+        ));
+    }
+
+    #[test]
+    fn from_base32_invalid_character() {
+        match Authenticator::from_base32("MZQW1ZLTMVRXEZLU", 6) {
+            Err(Error::InvalidSecret(message)) => {
+                assert_eq!(message, "invalid character '1' at position 4")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!(
+            Authenticator::from_base32("mzqw wzlt mvrx ezlu", 6)
+                .unwrap()
+                .generate_hotp(0),
+            937044
+        );
+    }
+
+    #[test]
+    fn from_token_url_label() {
+        let token_url = "otpauth://totp/Example:alice%40example.com?secret=MZQWWZLTMVRXEZLU";
+        let authenticator = Authenticator::from_token_url(token_url).unwrap();
+        assert_eq!(authenticator.issuer(), Some("Example"));
+        assert_eq!(authenticator.account(), Some("alice@example.com"));
+
+        let token_url = "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&issuer=Example";
+        let authenticator = Authenticator::from_token_url(token_url).unwrap();
+        assert_eq!(authenticator.issuer(), Some("Example"));
+        assert_eq!(authenticator.account(), Some("alice"));
+    }
+
+    #[test]
+    fn parse_label() {
+        assert_eq!(
+            super::parse_label("Example:alice"),
+            (Some("Example".to_string()), "alice".to_string())
+        );
+        assert_eq!(super::parse_label("alice"), (None, "alice".to_string()));
+        assert_eq!(
+            super::parse_label("Big%20Corp%3A%20alice%20smith"),
+            (Some("Big Corp".to_string()), "alice smith".to_string())
+        );
+    }
+
+    #[test]
+    fn generate_hotp() {
+        assert_eq!(Authenticator::new("fakesecret", 6).generate_hotp(0), 937044);
+
+        assert_eq!(
+            Authenticator::new("fakesecret", 7).generate_hotp(0),
+            1937044
+        );
+
+        assert_eq!(
+            Authenticator::new("fakesecret", 8).generate_hotp(0),
+            41937044
+        );
+
+        assert_eq!(
+            Authenticator::new("fakesecret", 9).generate_hotp(0),
+            741937044
+        );
+    }
+
+    #[test]
+    fn generate_hotp_string() {
+        let six = Authenticator::new(RFC4226_SECRET, 6);
+        let eight = Authenticator::new(RFC4226_SECRET, 8);
+        for (counter, &(six_digits, eight_digits)) in RFC4226_VECTORS.iter().enumerate() {
+            assert_eq!(six.generate_hotp_string(counter as u64), six_digits);
+            assert_eq!(eight.generate_hotp_string(counter as u64), eight_digits);
+        }
+
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        assert_eq!(
+            authenticator.generate_totp_string_at(1111111109),
+            "07081804"
+        );
+        assert_eq!(authenticator.generate_totp_at(1111111109), 7081804);
+    }
+
+    #[test]
+    fn new_lenient() {
+        let authenticator = Authenticator::new_lenient(RFC4226_SECRET, 4).unwrap();
+        assert_eq!(authenticator.generate_hotp_string(0), "5224");
+        assert_eq!(authenticator.generate_hotp_string(7), "2583");
+
+        let authenticator = Authenticator::new_lenient(RFC4226_SECRET, 10).unwrap();
+        assert_eq!(authenticator.generate_hotp_string(0), "1284755224");
+        assert_eq!(authenticator.generate_hotp_string(7), "0082162583");
+
+        assert!(matches!(
+            Authenticator::new_lenient(RFC4226_SECRET, 3),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert!(matches!(
+            Authenticator::new_lenient(RFC4226_SECRET, 11),
+            Err(Error::InvalidDigits(_))
+        ));
+    }
+
+    #[test]
+    fn new_with_algorithm() {
+        assert_eq!(
+            Authenticator::new_with_algorithm(
+                "12345678901234567890123456789012",
+                8,
+                Algorithm::SHA256
+            )
+            .generate_totp_at(59),
+            46119246
+        );
+
+        assert_eq!(
+            Authenticator::new_with_algorithm(
+                "1234567890123456789012345678901234567890123456789012345678901234",
+                8,
+                Algorithm::SHA512
+            )
+            .generate_totp_at(59),
+            90693936
+        );
+    }
+
+    #[test]
+    fn verify_totp_any_algorithm() {
+        let code = Authenticator::new_with_algorithm(
+            "12345678901234567890123456789012",
+            8,
+            Algorithm::SHA256,
+        )
+        .generate_totp_at(59);
+
+        let authenticator = Authenticator::new("12345678901234567890123456789012", 8);
+        assert_eq!(
+            authenticator.verify_totp_any_algorithm_at(code, 1, 59),
+            Some(Algorithm::SHA256)
+        );
+        assert_eq!(
+            authenticator.verify_totp_any_algorithm_at(code, 1, 599),
+            None
+        );
+    }
+
+    #[test]
+    fn totp() {
+        for &(timestamp, sha1, sha256, sha512) in RFC6238_VECTORS.iter() {
+            assert_eq!(
+                super::totp(RFC6238_SHA1_SECRET, timestamp, 30, 8, Algorithm::SHA1).unwrap(),
+                sha1
+            );
+            assert_eq!(
+                super::totp(RFC6238_SHA256_SECRET, timestamp, 30, 8, Algorithm::SHA256).unwrap(),
+                sha256
+            );
+            assert_eq!(
+                super::totp(RFC6238_SHA512_SECRET, timestamp, 30, 8, Algorithm::SHA512).unwrap(),
+                sha512
+            );
+        }
+
+        assert!(matches!(
+            super::totp(RFC6238_SHA1_SECRET, 59, 30, 5, Algorithm::SHA1),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert!(matches!(
+            super::totp(RFC6238_SHA1_SECRET, 59, 0, 8, Algorithm::SHA1),
+            Err(Error::InvalidPeriod(_))
+        ));
+        assert!(matches!(
+            super::totp(b"", 59, 30, 8, Algorithm::SHA1),
+            Err(Error::InvalidSecret(_))
+        ));
+    }
+
+    #[test]
+    fn compute_offset_seconds() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+
+        let huge = authenticator.clone().with_period(u64::MAX).unwrap();
+        let next = huge.generate_hotp(1);
+        assert_eq!(huge.compute_offset_seconds_at(next, 1, 0), None);
+        let large = authenticator.clone().with_period(i64::MAX as u64).unwrap();
+        let before = large.generate_hotp(0);
+        assert_eq!(large.compute_offset_seconds_at(before, 2, u64::MAX), None);
+
+        let behind = authenticator.generate_totp_at(timestamp - 60);
+        assert_eq!(
+            authenticator.compute_offset_seconds_at(behind, 3, timestamp),
+            Some(-60)
+        );
+
+        let ahead = authenticator.generate_totp_at(timestamp + 30);
+        assert_eq!(
+            authenticator.compute_offset_seconds_at(ahead, 3, timestamp),
+            Some(30)
+        );
+
+        let current = authenticator.generate_totp_at(timestamp);
+        assert_eq!(
+            authenticator.compute_offset_seconds_at(current, 0, timestamp),
+            Some(0)
+        );
+        assert_eq!(
+            authenticator.compute_offset_seconds_at(behind, 1, timestamp),
+            None
+        );
+    }
+
+    #[test]
+    fn refresh_in() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        assert_eq!(authenticator.remaining_seconds_at(1_111_111_120), 20);
+        assert_eq!(
+            authenticator.refresh_in_at(1_111_111_120),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            authenticator.refresh_in_at(1_111_111_110),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            authenticator.refresh_in_at(1_111_111_139),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn codes_from() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let codes: Vec<(u64, String)> = authenticator.codes_from(1111111109).take(3).collect();
+        assert_eq!(codes.len(), 3);
+        for (i, (timestamp, code)) in codes.into_iter().enumerate() {
+            assert_eq!(timestamp, 1111111080 + 30 * i as u64);
+            assert_eq!(code, authenticator.generate_totp_string_at(timestamp));
+        }
+        assert_eq!(
+            authenticator.codes_from(1111111109).next(),
+            Some((1111111080, "07081804".to_string()))
+        );
+    }
+
+    #[test]
+    fn verify_totp_detailed() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+
+        let current = authenticator.generate_totp_at(timestamp);
+        assert_eq!(
+            authenticator.verify_totp_detailed_at(current, 1, timestamp),
+            VerifyResult::Valid {
+                step_offset: 0,
+                step: 53_333_333
+            }
+        );
+
+        let previous = authenticator.generate_totp_at(timestamp - 30);
+        assert_eq!(
+            authenticator.verify_totp_detailed_at(previous, 1, timestamp),
+            VerifyResult::Valid {
+                step_offset: -1,
+                step: 53_333_332
+            }
+        );
+        assert_eq!(
+            authenticator.verify_totp_detailed_at(previous, 0, timestamp),
+            VerifyResult::Invalid
+        );
+    }
+
+    #[test]
+    fn debug_redacts_secret() {
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        let debug = format!("{:?}", authenticator);
+        assert!(debug.contains("<redacted>"));
+        assert!(debug.contains("digits: 6"));
+        assert!(!debug.contains("MZQWWZLTMVRXEZLU"));
+        assert!(!debug.contains(&format!("{:?}", b"fakesecret".to_vec())));
+
+        let debug = authenticator.debug_with_secret();
+        assert!(debug.contains(&format!("{:?}", b"fakesecret".to_vec())));
+    }
+
+    #[test]
+    fn hash_and_eq() {
+        let mut set = HashSet::new();
+        set.insert(Authenticator::new("fakesecret", 6));
+        set.insert(Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap());
+        assert_eq!(set.len(), 1);
+
+        set.insert(Authenticator::new("fakesecret", 8));
+        set.insert(Authenticator::new("othersecret", 6));
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn secret_bytes() {
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        assert_eq!(authenticator.secret_bytes(), RFC4226_SECRET);
+
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        assert_eq!(authenticator.secret_bytes(), b"fakesecret");
+    }
+
+    #[test]
+    fn error_eq() {
+        assert_eq!(
+            Error::InvalidDigits("5".to_string()),
+            Error::InvalidDigits("5".to_string())
+        );
+        assert_ne!(
+            Error::InvalidDigits("5".to_string()),
+            Error::InvalidSecret("5".to_string())
+        );
+        assert_eq!(
+            Authenticator::from_token_url("otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&digits=5")
+                .unwrap_err(),
+            Error::InvalidDigits(
+                "5 is not valid digits for code length, must be between 6-9".to_string()
+            )
+        );
+        assert_eq!(
+            Authenticator::from_token_url("not a url").unwrap_err(),
+            Error::InvalidTokenURL(url::ParseError::RelativeUrlWithoutBase)
+        );
+    }
+
+    #[test]
+    fn authenticator_set_into_iter() {
+        let mut set = AuthenticatorSet::new();
+        set.insert("alice", Authenticator::new("fakesecret", 6));
+        set.insert("bob", Authenticator::new("othersecret", 6));
+        set.insert("carol", Authenticator::new(RFC4226_SECRET, 8));
+
+        let mut count = 0;
+        for (_, authenticator) in &set {
+            assert!(authenticator.generate_hotp_string(0).len() >= 6);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        let labels: Vec<String> = set.into_iter().map(|(label, _)| label).collect();
+        assert_eq!(labels, vec!["alice", "bob", "carol"]);
+    }
+
+    #[test]
+    fn zero_period() {
+        assert_eq!(
+            Authenticator::new("fakesecret", 6)
+                .with_period(0)
+                .unwrap_err(),
+            Error::InvalidPeriod("period must be greater than 0".to_string())
+        );
+        assert_eq!(
+            Authenticator::from_token_url("otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&period=0")
+                .unwrap_err(),
+            Error::InvalidPeriod("period must be greater than 0".to_string())
+        );
+
+        let authenticator =
+            Authenticator::from_token_url("otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&period=60")
+                .unwrap();
+        assert_eq!(authenticator.period(), 60);
+        assert_eq!(
+            authenticator.generate_totp_at(59),
+            authenticator.generate_hotp(0)
+        );
+    }
+
+    #[test]
+    fn generate_totp_honors_algorithm_and_period() {
+        fn reference(secret: &[u8], timestamp: u64) -> u32 {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+            let tag = hmac::sign(&key, &(timestamp / 60).to_be_bytes());
+            let digest = tag.as_ref();
+            let offset = (digest[31] & 0xf) as usize;
+            let binary = ((digest[offset] as u32 & 0x7f) << 24)
+                | ((digest[offset + 1] as u32) << 16)
+                | ((digest[offset + 2] as u32) << 8)
+                | (digest[offset + 3] as u32);
+            binary % 1_000_000
+        }
+
+        let token_url = "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&algorithm=SHA256&period=60";
+        let authenticator = Authenticator::from_token_url(token_url).unwrap();
+        assert_eq!(authenticator.algorithm(), Algorithm::SHA256);
+        assert_eq!(authenticator.period(), 60);
+
+        let sha1 = Authenticator::new("fakesecret", 6).with_period(60).unwrap();
+        for &timestamp in &[0, 59, 60, 1111111109, 2000000000] {
+            assert_eq!(
+                authenticator.generate_totp_at(timestamp),
+                reference(b"fakesecret", timestamp)
+            );
+            assert_ne!(
+                authenticator.generate_totp_at(timestamp),
+                sha1.generate_totp_at(timestamp)
+            );
+        }
+
+        assert_eq!(
+            Authenticator::from_token_url(
+                "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&algorithm=MD5"
+            )
+            .unwrap_err(),
+            Error::InvalidAlgorithm("MD5 is not a supported algorithm".to_string())
+        );
+    }
+
+    #[test]
+    fn to_token_url() {
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        assert_eq!(
+            authenticator.to_token_url("alice@example.com"),
+            "otpauth://totp/alice%40example.com?secret=MZQWWZLTMVRXEZLU"
+        );
+
+        let authenticator = Authenticator::new_with_algorithm("fakesecret", 8, Algorithm::SHA256)
+            .with_period(60)
+            .unwrap();
+        assert_eq!(
+            authenticator.to_token_url("alice"),
+            "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&algorithm=SHA256&digits=8&period=60"
+        );
+    }
+
+    #[test]
+    fn to_google_url() {
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        let google_url = authenticator.to_google_url("alice@example.com", "Example Co");
+        assert_eq!(
+            google_url,
+            "otpauth://totp/Example%20Co:alice%40example.com?secret=MZQWWZLTMVRXEZLU\
+             &issuer=Example%20Co&algorithm=SHA1&digits=6&period=30"
+        );
+
+        let imported = Authenticator::from_token_url(google_url).unwrap();
+        assert_eq!(imported.issuer(), Some("Example Co"));
+        assert_eq!(imported.account(), Some("alice@example.com"));
+        assert_eq!(imported.secret_bytes(), authenticator.secret_bytes());
+        assert_eq!(imported.generate_hotp(0), 937044);
+    }
+
+    #[test]
+    fn parse_counter() {
+        assert_eq!(super::parse_counter("42"), Ok(42));
+        assert_eq!(super::parse_counter("0000042"), Ok(42));
+        assert_eq!(super::parse_counter("0x2A"), Ok(42));
+        assert_eq!(super::parse_counter("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(
+            super::parse_counter("18446744073709551616"),
+            Err(Error::InvalidCounter(
+                "18446744073709551616: number too large to fit in target type".to_string()
+            ))
+        );
+        assert!(super::parse_counter("0x10000000000000000").is_err());
+        assert!(super::parse_counter("-1").is_err());
+
+        let token_url = "otpauth://hotp/alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=007";
+        assert_eq!(
+            Authenticator::from_token_url(token_url).unwrap().counter(),
+            7
+        );
+    }
+
+    #[test]
+    fn verify_totp_consecutive() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+        let previous = authenticator.generate_totp_at(timestamp - 30);
+        let current = authenticator.generate_totp_at(timestamp);
+        let next = authenticator.generate_totp_at(timestamp + 30);
+
+        assert!(authenticator.verify_totp_consecutive_at(previous, current, timestamp));
+        assert!(authenticator.verify_totp_consecutive_at(current, next, timestamp));
+        assert!(!authenticator.verify_totp_consecutive_at(previous, next, timestamp));
+        assert!(!authenticator.verify_totp_consecutive_at(current, previous, timestamp));
+        assert!(!authenticator.verify_totp_consecutive_at(current, current, timestamp));
+    }
+
+    #[test]
+    fn verify_totp_window() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+
+        let valid = authenticator.valid_codes_at(2, timestamp);
+        let expected: Vec<String> = (-2..=2)
+            .map(|offset: i64| {
+                authenticator.generate_totp_string_at((timestamp as i64 + offset * 30) as u64)
+            })
+            .collect();
+        assert_eq!(valid, expected);
+
+        for offset in -4..=4i64 {
+            let code = authenticator.generate_totp_at((timestamp as i64 + offset * 30) as u64);
+            assert_eq!(
+                authenticator.verify_totp_window_at(code, 2, timestamp),
+                offset.abs() <= 2
+            );
+        }
+
+        assert_eq!(authenticator.valid_codes_at(1, 0).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to generate a code with zero digits")]
+    fn zero_digits() {
+        Authenticator::new("fakesecret", 0).generate_hotp(0);
+    }
+
+    #[test]
+    fn zero_digits_rejected_by_fallible_constructors() {
+        assert!(Authenticator::from_base32("MZQWWZLTMVRXEZLU", 0).is_err());
+        assert!(Authenticator::new_lenient("fakesecret", 0).is_err());
+        assert!(Authenticator::from_token_url(
+            "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&digits=0"
+        )
+        .is_err());
+        assert!(AuthenticatorBuilder::new()
+            .secret("fakesecret")
+            .digits(0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to generate a code with zero digits")]
+    fn zero_digits_formatted() {
+        Authenticator::new("fakesecret", 0).generate_totp_formatted_at(&PlainFormatter, 0);
+    }
+
+    #[test]
+    fn authenticator_builder_default() {
+        let authenticator = AuthenticatorBuilder::default()
+            .secret_base32("MZQWWZLTMVRXEZLU")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(authenticator.algorithm(), Algorithm::SHA1);
+        assert_eq!(authenticator.digits(), 6);
+        assert_eq!(authenticator.period(), 30);
+        assert_eq!(authenticator.generate_hotp(0), 937044);
+        assert_eq!(authenticator, Authenticator::new("fakesecret", 6));
+
+        assert_eq!(
+            AuthenticatorBuilder::default().build().unwrap_err(),
+            Error::InvalidSecret("empty secret".to_string())
+        );
+        assert!(matches!(
+            AuthenticatorBuilder::default()
+                .secret("fakesecret")
+                .digits(5)
+                .build(),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert!(matches!(
+            AuthenticatorBuilder::default()
+                .secret("fakesecret")
+                .period(0)
+                .build(),
+            Err(Error::InvalidPeriod(_))
+        ));
+    }
+
+    // The futures under test resolve immediately, so a single poll with a
+    // no-op waker is enough.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+        match Box::pin(future).as_mut().poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("test future was not ready"),
+        }
+    }
+
+    #[test]
+    fn verify_totp_async() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+        let code = authenticator.generate_totp_at(timestamp);
+
+        assert!(block_on(authenticator.verify_totp_async(
+            code,
+            0,
+            future::ready(timestamp)
+        )));
+        assert!(!block_on(authenticator.verify_totp_async(
+            code,
+            0,
+            future::ready(timestamp + 60)
+        )));
+    }
+
+    #[test]
+    fn dynamic_truncate() {
+        // RFC 4226 section 5.4
+        let digest = [
+            0x1f, 0x86, 0x98, 0x69, 0x0e, 0x02, 0xca, 0x16, 0x61, 0x85, 0x50, 0xef, 0x7f, 0x19,
+            0xda, 0x8e, 0x94, 0x5b, 0x55, 0x5a,
+        ];
+        assert_eq!(super::dynamic_truncate(&digest, None), 0x50ef_7f19);
+        assert_eq!(super::dynamic_truncate(&digest, Some(10)), 0x50ef_7f19);
+        assert_eq!(super::dynamic_truncate(&digest, Some(0)), 0x1f86_9869);
+        assert_eq!(super::dynamic_truncate(&digest, Some(16)), 0x145b_555a);
+
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        assert_eq!(
+            authenticator.generate_hotp_with_offset(0, None),
+            authenticator.generate_hotp(0)
+        );
+        assert_eq!(authenticator.generate_hotp_with_offset(1, Some(0)), 717529);
+        assert_eq!(authenticator.generate_hotp_with_offset(1, None), 287082);
+    }
+
+    #[test]
+    fn config() {
+        let token_url = "otpauth://hotp/alice?secret=MZQWWZLTMVRXEZLU&algorithm=SHA512&digits=8";
+        let config = Authenticator::from_token_url(token_url).unwrap().config();
+        assert_eq!(
+            config,
+            OtpConfig {
+                digits: 8,
+                period: 30,
+                algorithm: Algorithm::SHA512,
+                otp_type: OtpType::Hotp,
+            }
+        );
+
+        let authenticator = AuthenticatorBuilder::default()
+            .secret("fakesecret")
+            .period(60)
+            .build()
+            .unwrap();
+        assert_eq!(authenticator.config().period, 60);
+    }
+
+    #[test]
+    fn enrollment_check() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+        let code_at =
+            |offset: i64| authenticator.generate_totp_at((timestamp as i64 + offset) as u64);
+
+        assert_eq!(
+            authenticator.enrollment_check_at(code_at(0), timestamp),
+            EnrollmentResult::Correct
+        );
+        assert_eq!(
+            authenticator.enrollment_check_at(code_at(-60), timestamp),
+            EnrollmentResult::CorrectButClockBehind(60)
+        );
+        assert_eq!(
+            authenticator.enrollment_check_at(code_at(90), timestamp),
+            EnrollmentResult::CorrectButClockAhead(90)
+        );
+        assert_eq!(
+            authenticator.enrollment_check_at(code_at(-600), timestamp),
+            EnrollmentResult::Incorrect
+        );
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn from_zeroizing() {
+        let secret = zeroize::Zeroizing::new(RFC4226_SECRET.to_vec());
+        let authenticator = Authenticator::from_zeroizing(secret, 6);
+        assert_eq!(authenticator.generate_hotp_string(0), RFC4226_VECTORS[0].0);
+        assert_eq!(authenticator.secret_bytes(), RFC4226_SECRET);
+    }
+
+    #[test]
+    fn verify_totp_in_range() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let code = authenticator.generate_totp_at(1_600_000_025);
+
+        assert!(authenticator.verify_totp_in_range(code, 1_600_000_000, 1_600_000_100));
+        assert!(authenticator.verify_totp_in_range(code, 1_600_000_049, 1_600_000_049));
+        assert!(authenticator.verify_totp_in_range(code, 1_599_999_900, 1_600_000_020));
+        assert!(!authenticator.verify_totp_in_range(code, 1_600_000_050, 1_600_000_200));
+        assert!(!authenticator.verify_totp_in_range(code, 1_599_999_900, 1_600_000_019));
+        assert!(!authenticator.verify_totp_in_range(code, 1_600_000_100, 1_600_000_000));
+        assert!(!authenticator.verify_totp_in_range(code, 0, u64::MAX));
+        assert!(!authenticator.verify_totp_in_range(code, 1_600_000_000, 1_603_000_000));
+    }
+
+    #[test]
+    fn from_token_url_verbose() {
+        let token_url = "otpauth://totp/Example:alice?secret=MZQWWZLTMVRXEZLU&issuer=Example\
+                         &image=https%3A%2F%2Fexample.com%2Flogo.png&counter=5";
+        let (authenticator, warnings) = Authenticator::from_token_url_verbose(token_url).unwrap();
+        assert_eq!(authenticator.generate_hotp(0), 937044);
+        assert_eq!(
+            warnings,
+            vec![
+                "unsupported parameter \"image\" ignored".to_string(),
+                "parameter \"counter\" ignored for this type".to_string(),
+            ]
+        );
+
+        let token_url = "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU";
+        let (_, warnings) = Authenticator::from_token_url_verbose(token_url).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn generate_hotp_counter_width() {
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        for counter in 0..10 {
+            assert_eq!(
+                authenticator.generate_hotp_counter_width(counter, 8),
+                authenticator.generate_hotp(counter)
+            );
+        }
+
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, RFC4226_SECRET);
+        assert_eq!(
+            authenticator.generate_hotp_counter_width(1, 4),
+            super::sign_and_truncate(&key, &[0, 0, 0, 1], 6, None)
+        );
+        assert_eq!(
+            authenticator.generate_hotp_counter_width(1, 10),
+            super::sign_and_truncate(&key, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 1], 6, None)
+        );
+        assert_ne!(
+            authenticator.generate_hotp_counter_width(1, 4),
+            authenticator.generate_hotp(1)
+        );
+    }
+
+    #[test]
+    fn otpauth_url() {
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        let mut url = authenticator.otpauth_url("alice smith", Some("Example"));
+        assert_eq!(url.scheme(), "otpauth");
+        assert_eq!(url.host_str(), Some("totp"));
+        assert_eq!(url.path(), "/Example:alice%20smith");
+        assert!(url
+            .query_pairs()
+            .any(|(k, v)| k == "secret" && v == "MZQWWZLTMVRXEZLU"));
+
+        url.query_pairs_mut().append_pair("image", "logo.png");
+        let (_, warnings) = Authenticator::from_token_url_verbose(url.as_str()).unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        let url = authenticator.otpauth_url("alice", None);
+        assert_eq!(url.query(), Some("secret=MZQWWZLTMVRXEZLU"));
+    }
+
+    #[test]
+    fn verify_context() {
+        let authenticator =
+            Authenticator::new_with_algorithm(RFC6238_SHA256_SECRET, 8, Algorithm::SHA256)
+                .with_period(60)
+                .unwrap();
+        let context = authenticator.verify_context();
+        let timestamp = 1_600_000_000;
+
+        for offset in -3..=3i64 {
+            let code = authenticator.generate_totp_at((timestamp as i64 + offset * 60) as u64);
+            assert_eq!(
+                context.verify(code, 2, timestamp),
+                authenticator.verify_totp_window_at(code, 2, timestamp)
+            );
+            assert_eq!(
+                context.verify_detailed(code, 2, timestamp),
+                authenticator.verify_totp_detailed_at(code, 2, timestamp)
+            );
+        }
+        assert!(!context.verify(0, 2, timestamp));
+    }
+
+    #[test]
+    fn next_boundary() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        assert_eq!(authenticator.next_boundary_at(1_111_111_109), 1_111_111_110);
+        assert_eq!(authenticator.next_boundary_at(1_111_111_110), 1_111_111_140);
+        assert_eq!(authenticator.next_boundary_at(1_111_111_111), 1_111_111_140);
+
+        let authenticator = authenticator.with_period(60).unwrap();
+        assert_eq!(authenticator.next_boundary_at(1_111_111_111), 1_111_111_140);
+        assert_eq!(authenticator.next_boundary_at(0), 60);
+    }
+
+    #[test]
+    fn algorithm_from_str() {
+        assert_eq!("sha1".parse::<Algorithm>(), Ok(Algorithm::SHA1));
+        assert_eq!("SHA-256".parse::<Algorithm>(), Ok(Algorithm::SHA256));
+        assert_eq!("Sha512".parse::<Algorithm>(), Ok(Algorithm::SHA512));
+        assert_eq!("SHA256".parse::<Algorithm>(), Ok(Algorithm::SHA256));
+        assert_eq!(
+            "md5".parse::<Algorithm>(),
+            Err(Error::InvalidAlgorithm(
+                "md5 is not a supported algorithm".to_string()
+            ))
+        );
+
+        let token_url = "otpauth://totp/alice?secret=MZQWWZLTMVRXEZLU&algorithm=sha-512";
+        assert_eq!(
+            Authenticator::from_token_url(token_url)
+                .unwrap()
+                .algorithm(),
+            Algorithm::SHA512
+        );
+    }
+
+    #[test]
+    fn verify_str() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let counter = 1111111109 / 30;
+        assert_eq!(authenticator.generate_hotp_string(counter), "07081804");
+
+        assert_eq!(
+            authenticator.verify_hotp_str(counter - 6, "07081804", 10),
+            Some(counter)
+        );
+        assert_eq!(
+            authenticator.verify_hotp_str(counter, " 0708 1804 ", 0),
+            Some(counter)
+        );
+        assert_eq!(authenticator.verify_hotp_str(counter, "7081804", 10), None);
+        assert_eq!(
+            authenticator.verify_hotp_str(counter - 6, "07081804", 5),
+            None
+        );
+        assert_eq!(authenticator.verify_hotp_str(counter, "0708180a", 0), None);
+        assert_eq!(
+            authenticator.verify_hotp_str(counter, "07081804", u64::MAX),
+            None
+        );
+
+        assert!(authenticator.verify_totp_str_at("07081804", 0, 1111111109));
+        assert!(authenticator.verify_totp_str_at("0708 1804", 1, 1111111139));
+        assert!(!authenticator.verify_totp_str_at("7081804", 1, 1111111109));
+    }
+
+    #[test]
+    fn self_test() {
+        assert_eq!(super::self_test(), Ok(()));
+    }
+
+    #[test]
+    fn from_encoded_secret() {
+        let encodings = [
+            ("MZQWWZLTMVRXEZLU", Encoding::Base32),
+            ("CPGMMPBJCLHN4PBK", Encoding::Base32Hex),
+            ("66616b65736563726574", Encoding::Hex),
+            ("ZmFrZXNlY3JldA==", Encoding::Base64),
+            ("fakesecret", Encoding::Raw),
+        ];
+        for &(encoded, encoding) in encodings.iter() {
+            let authenticator = Authenticator::from_encoded_secret(encoded, encoding, 6).unwrap();
+            assert_eq!(authenticator.secret_bytes(), b"fakesecret");
+            assert_eq!(authenticator.generate_hotp(0), 937044);
+        }
+
+        assert!(Authenticator::from_encoded_secret("abc", Encoding::Hex, 6).is_err());
+        assert!(Authenticator::from_encoded_secret("zz", Encoding::Hex, 6).is_err());
+        assert!(Authenticator::from_encoded_secret("WXYZ", Encoding::Base32Hex, 6).is_err());
+        assert!(Authenticator::from_encoded_secret("!!", Encoding::Base64, 6).is_err());
+    }
+
+    #[test]
+    fn from_token_url_without_label() {
+        for token_url in &[
+            "otpauth://totp/?secret=MZQWWZLTMVRXEZLU",
+            "otpauth://totp?secret=MZQWWZLTMVRXEZLU",
+            "otpauth://totp/:?secret=MZQWWZLTMVRXEZLU",
+        ] {
+            let authenticator = Authenticator::from_token_url(*token_url).unwrap();
+            assert_eq!(authenticator.secret_bytes(), b"fakesecret");
+            assert_eq!(authenticator.account(), None);
+            assert_eq!(authenticator.issuer(), None);
+        }
+
+        for token_url in &[
+            "otpauth://totp/?secret=MZQWWZLTMVRXEZLU&issuer=Example&digits=8",
+            "otpauth://totp/:?secret=MZQWWZLTMVRXEZLU&issuer=Example&digits=8",
+        ] {
+            let authenticator = Authenticator::from_token_url(*token_url).unwrap();
+            assert_eq!(authenticator.account(), None);
+            assert_eq!(authenticator.issuer(), Some("Example"));
+            assert_eq!(authenticator.digits(), 8);
+        }
+    }
+
+    #[test]
+    fn generate_totp_with_step() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let (step, code) = authenticator.generate_totp_with_step_at(1111111109);
+        assert_eq!(step, super::time_step(1111111109, 30, 0));
+        assert_eq!(step, 37037036);
+        assert_eq!(code, "07081804");
+
+        assert_eq!(super::time_step(100, 30, 40), 2);
+        assert_eq!(super::time_step(10, 30, 40), 0);
+    }
+
+    #[test]
+    fn from_base32_digits() {
+        assert!(matches!(
+            Authenticator::from_base32("MZQWWZLTMVRXEZLU", 5),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert!(matches!(
+            Authenticator::from_base32("MZQWWZLTMVRXEZLU", 11),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert!(matches!(
+            Authenticator::from_base32("MZQWWZLTMVRXEZLU", Digits(10)),
+            Err(Error::InvalidDigits(_))
+        ));
+        assert_eq!(
+            Authenticator::from_base32("MZQWWZLTMVRXEZLU", Digits(8))
+                .unwrap()
+                .generate_hotp(0),
+            41937044
+        );
+    }
+
+    #[test]
+    fn generate_totp_formatted() {
+        struct Reversed;
+
+        impl CodeFormatter for Reversed {
+            fn format(&self, truncated: u32, digits: u8) -> String {
+                PlainFormatter
+                    .format(truncated, digits)
+                    .chars()
+                    .rev()
+                    .collect()
+            }
+        }
+
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let timestamp = 1111111109;
+        assert_eq!(
+            authenticator.generate_totp_formatted_at(&PlainFormatter, timestamp),
+            "07081804"
+        );
+        assert_eq!(
+            authenticator.generate_totp_formatted_at(&GroupedFormatter::default(), timestamp),
+            "070 818 04"
+        );
+        assert_eq!(
+            authenticator.generate_totp_formatted_at(
+                &GroupedFormatter {
+                    group_size: 4,
+                    separator: '-'
+                },
+                timestamp
+            ),
+            "0708-1804"
+        );
+        assert_eq!(
+            authenticator.generate_totp_formatted_at(&Reversed, timestamp),
+            "40818070"
+        );
+
+        let steam = authenticator.generate_totp_formatted_at(&SteamFormatter, timestamp);
+        assert_eq!(steam.len(), 5);
+        assert!(steam
+            .chars()
+            .all(|c| "23456789BCDFGHJKMNPQRTVWXY".contains(c)));
+    }
+
+    #[test]
+    fn verify_totp() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_600_000_000;
+        let previous = authenticator.generate_totp_at(timestamp - 30);
+        let current = authenticator.generate_totp_at(timestamp);
+        let next = authenticator.generate_totp_at(timestamp + 30);
+
+        assert!(authenticator.verify_totp_at(current, timestamp));
+        assert!(authenticator.verify_totp_at(previous, timestamp));
+        assert!(!authenticator.verify_totp_at(next, timestamp));
+
+        assert!(authenticator.verify_totp_strict_at(current, timestamp));
+        assert!(!authenticator.verify_totp_strict_at(previous, timestamp));
+        assert!(!authenticator.verify_totp_strict_at(next, timestamp));
+
+        assert!(authenticator.verify_totp_at(authenticator.generate_totp_at(0), 0));
+    }
+
+    #[test]
+    fn look_ahead() {
+        let mut authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        let code = |counter: usize| RFC4226_VECTORS[counter].0.parse::<u32>().unwrap();
+
+        assert_eq!(authenticator.verify_hotp_window(code(9), None), Some(9));
+        assert!(authenticator.verify_and_advance(code(3)));
+        assert_eq!(authenticator.counter(), 4);
+        assert!(!authenticator.verify_and_advance(code(3)));
+
+        let mut authenticator = AuthenticatorBuilder::default()
+            .secret(RFC4226_SECRET)
+            .look_ahead(2)
+            .build()
+            .unwrap();
+        assert_eq!(authenticator.verify_hotp_window(code(3), None), None);
+        assert_eq!(authenticator.verify_hotp_window(code(3), Some(3)), Some(3));
+        assert_eq!(
+            authenticator.verify_hotp_window(code(3), Some(u64::MAX)),
+            None
+        );
+        assert_eq!(authenticator.verify_hotp_window(code(3), Some(1001)), None);
+        assert!(!authenticator.verify_and_advance(code(3)));
+        assert!(authenticator.verify_and_advance(code(2)));
+        assert_eq!(authenticator.counter(), 3);
+
+        assert!(matches!(
+            AuthenticatorBuilder::default()
+                .secret(RFC4226_SECRET)
+                .look_ahead(1_000_000)
+                .build(),
+            Err(Error::InvalidLookAhead(_))
+        ));
+        assert!(Authenticator::new(RFC4226_SECRET, 6)
+            .with_look_ahead(1001)
+            .is_err());
+    }
+
+    #[test]
+    fn codes_for_window() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let codes = authenticator
+            .codes_for_window(1111111109, 1111111111 + 3600)
+            .unwrap();
+        assert_eq!(codes.len(), 122);
+        assert_eq!(codes[0], (1111111080, "07081804".to_string()));
+        assert_eq!(codes[1], (1111111110, "14050471".to_string()));
+        assert_eq!(
+            codes[121],
+            (
+                1111114710,
+                authenticator.generate_totp_string_at(1111114711)
+            )
+        );
+
+        assert_eq!(authenticator.codes_for_window(59, 59).unwrap().len(), 1);
+        assert!(matches!(
+            authenticator.codes_for_window(60, 59),
+            Err(Error::InvalidRange(_))
+        ));
+        assert!(matches!(
+            authenticator.codes_for_window(0, 30 * 100_000),
+            Err(Error::InvalidRange(_))
+        ));
+        assert!(matches!(
+            Authenticator::new(RFC6238_SHA1_SECRET, 8)
+                .with_period(1)
+                .unwrap()
+                .codes_for_window(0, u64::MAX),
+            Err(Error::InvalidRange(_))
+        ));
+    }
+
+    #[test]
+    fn verify_str_malformed() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let timestamp = 1111111109;
+
+        for code in &[
+            "070818041",
+            "07081804000000000000",
+            "0708180",
+            "",
+            "   ",
+            "0708180x",
+            "07081804\u{0}",
+            "\u{663}7081804",
+        ] {
+            assert!(!authenticator.verify_totp_str_at(code, 1, timestamp));
+            assert_eq!(authenticator.verify_hotp_str(37037036, code, 1), None);
+        }
+        assert!(authenticator.verify_totp_str_at("07081804", 1, timestamp));
+    }
+
+    #[test]
+    fn from_hmac_key() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, RFC6238_SHA256_SECRET);
+        let authenticator = Authenticator::from_hmac_key(RFC6238_SHA256_SECRET, 8, key).unwrap();
+        let expected =
+            Authenticator::new_with_algorithm(RFC6238_SHA256_SECRET, 8, Algorithm::SHA256);
+        assert_eq!(authenticator, expected);
+        for &(timestamp, _, sha256, _) in RFC6238_VECTORS.iter() {
+            assert_eq!(authenticator.generate_totp_string_at(timestamp), sha256);
+            assert_eq!(
+                authenticator.generate_totp_at(timestamp),
+                expected.generate_totp_at(timestamp)
+            );
+        }
+
+        let key = hmac::Key::new(hmac::HMAC_SHA384, RFC6238_SHA256_SECRET);
+        assert!(matches!(
+            Authenticator::from_hmac_key(RFC6238_SHA256_SECRET, 8, key),
+            Err(Error::InvalidAlgorithm(_))
+        ));
+
+        let key = hmac::Key::new(hmac::HMAC_SHA256, RFC6238_SHA256_SECRET);
+        assert!(matches!(
+            Authenticator::from_hmac_key(RFC6238_SHA256_SECRET, 0, key),
+            Err(Error::InvalidDigits(_))
+        ));
+    }
+
+    #[test]
+    fn validate() {
+        assert!(super::validate_digits(5).is_err());
+        assert_eq!(super::validate_digits(6), Ok(()));
+        assert_eq!(super::validate_digits(9), Ok(()));
+        assert!(super::validate_digits(10).is_err());
+        assert!(super::validate_digits(0).is_err());
+
+        assert!(super::validate_period(0).is_err());
+        assert_eq!(super::validate_period(1), Ok(()));
+        assert_eq!(super::validate_period(u64::MAX), Ok(()));
+    }
+
+    #[test]
+    fn generate_totp_with_algorithm() {
+        let authenticator = Authenticator::new(RFC6238_SHA256_SECRET, 8);
+        assert_eq!(
+            authenticator.generate_totp_with_algorithm(Algorithm::SHA256, 59),
+            46119246
+        );
+        assert_ne!(
+            authenticator.generate_totp_with_algorithm(Algorithm::SHA256, 59),
+            authenticator.generate_totp_at(59)
+        );
+        assert_eq!(
+            authenticator.generate_totp_with_algorithm(Algorithm::SHA1, 59),
+            authenticator.generate_totp_at(59)
+        );
+        assert_eq!(authenticator.algorithm(), Algorithm::SHA1);
+    }
+
+    #[test]
+    fn hotp_codes() {
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        let codes = authenticator.hotp_codes(3, 5);
+        assert_eq!(codes.len(), 5);
+        assert_eq!(codes[0], (3, authenticator.generate_hotp_string(3)));
+        for (i, (counter, code)) in codes.iter().enumerate() {
+            assert_eq!(*counter, 3 + i as u64);
+            assert_eq!(code, RFC4226_VECTORS[3 + i].0);
+        }
+
+        assert_eq!(authenticator.hotp_codes(u64::MAX, 5).len(), 1);
+        assert!(authenticator.hotp_codes(0, 0).is_empty());
+    }
+
+    #[test]
+    fn authenticator_set_duplicates() {
+        let mut set = AuthenticatorSet::new();
+        set.insert("alice", Authenticator::new("fakesecret", 6));
+        set.insert("bob", Authenticator::new("othersecret", 6));
+        set.insert("work", Authenticator::new("fakesecret", 8));
+        assert_eq!(set.duplicates().len(), 1);
+
+        let (first, second) = set.duplicates()[0];
+        assert_eq!(first, set.get("alice").unwrap());
+        assert_eq!(second, set.get("work").unwrap());
+
+        set.insert("home", Authenticator::new("fakesecret", 6));
+        assert_eq!(set.duplicates().len(), 3);
+    }
+
+    #[test]
+    fn from_token_url_lenient() {
+        let url = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&digits=abc&period=0";
+        assert!(Authenticator::from_token_url(url).is_err());
+        let auth = Authenticator::from_token_url_lenient(url).unwrap();
+        assert_eq!(auth.digits(), DEFAULT_DIGITS);
+        assert_eq!(auth.period(), DEFAULT_PERIOD);
+
+        let missing = "otpauth://totp/Example:alice?digits=abc";
+        assert!(Authenticator::from_token_url_lenient(missing).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn generate_totp_at_datetime() {
+        use chrono::{TimeZone, Utc};
+
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let (timestamp, sha1, _, _) = RFC6238_VECTORS[1];
+        let dt = Utc.timestamp_opt(timestamp as i64, 0).unwrap();
+        assert_eq!(authenticator.generate_totp_at_datetime(dt).unwrap(), sha1);
+
+        let before_epoch = Utc.timestamp_opt(-1, 0).unwrap();
+        assert!(authenticator
+            .generate_totp_at_datetime(before_epoch)
+            .is_err());
+    }
+
+    #[test]
+    fn verifiers_at_fixed_timestamp() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_111_111_110;
+        let previous = authenticator.generate_totp_at(timestamp - 30);
+        let current = authenticator.generate_totp_at(timestamp);
+        let next = authenticator.generate_totp_at(timestamp + 30);
+
+        assert!(authenticator.verify_totp_at(previous, timestamp));
+        assert!(authenticator.verify_totp_strict_at(current, timestamp));
+        assert!(!authenticator.verify_totp_strict_at(previous, timestamp));
+        assert!(authenticator.verify_totp_window_at(next, 1, timestamp));
+        assert!(!authenticator.verify_totp_window_at(next, 0, timestamp));
+
+        assert!(authenticator.verify_totp_asymmetric_at(previous, 1, 0, timestamp));
+        assert!(!authenticator.verify_totp_asymmetric_at(next, 1, 0, timestamp));
+        assert!(authenticator.verify_totp_asymmetric_at(next, 0, 1, timestamp));
+
+        assert_eq!(
+            authenticator.verify_totp_detailed_at(next, 1, timestamp),
+            VerifyResult::Valid {
+                step_offset: 1,
+                step: 37_037_038
+            }
+        );
+        assert_eq!(
+            authenticator.compute_offset_seconds_at(previous, 1, timestamp),
+            Some(-30)
+        );
+    }
+
+    #[test]
+    fn secret_hint() {
+        let authenticator = Authenticator::from_base32("MZQWWZLTMVRXEZLU", 6).unwrap();
+        let hint = authenticator.secret_hint();
+        assert_eq!(hint, "MZ…LU");
+        assert!(hint.chars().count() <= 5);
+        assert!(hint.contains('…'));
+
+        let short = Authenticator::new("abc", 6);
+        assert_eq!(short.secret_hint(), "…");
+    }
+
+    #[test]
+    fn verify_totp_any_digits() {
+        let six = Authenticator::new("fakesecret", 6);
+        let eight = Authenticator::new("fakesecret", 8);
+        let timestamp = 1_111_111_110;
+        let code = eight.generate_totp_string_at(timestamp);
+        assert!(six.verify_totp_any_digits_at(&code, &[6, 8], 1, timestamp));
+        assert!(!six.verify_totp_any_digits_at(&code, &[6], 1, timestamp));
+        assert!(!six.verify_totp_any_digits_at(&code, &[6, 8], 0, timestamp + 60));
+        assert!(six.verify_totp_any_digits_at(
+            &six.generate_totp_string_at(timestamp),
+            &[6],
+            1,
+            timestamp
+        ));
+
+        let four = Authenticator::new_lenient("fakesecret", 4).unwrap();
+        let short = four.generate_totp_string_at(timestamp);
+        assert!(!six.verify_totp_any_digits_at(&short, &[1, 4], 1, timestamp));
+        assert!(!six.verify_totp_any_digits_at(&short[..1], &[1], 255, timestamp));
+        assert!(!six.verify_totp_any_digits_at("1234a678", &[8], 1, timestamp));
+    }
+
+    #[test]
+    fn modulus() {
+        for digits in 4..=10 {
+            let authenticator = Authenticator::new_lenient("fakesecret", digits).unwrap();
+            assert_eq!(
+                super::modulus(authenticator.digits()),
+                10u64.pow(digits as u32)
+            );
+            assert!((authenticator.generate_hotp(0) as u64) < super::modulus(digits));
+        }
+        assert_eq!(super::modulus(u8::MAX), u64::MAX);
+
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        for (counter, (code, _)) in RFC4226_VECTORS.iter().enumerate() {
+            assert_eq!(authenticator.generate_hotp_string(counter as u64), *code);
+        }
+    }
+
+    #[test]
+    fn streaming_verifier() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_111_111_110;
+        let code = authenticator.generate_totp_string_at(timestamp);
+        let mut verifier = StreamingVerifier::new_at(&authenticator, 1, timestamp);
+        assert_eq!(verifier.push(' '), None);
+        for (i, c) in code.chars().enumerate() {
+            let result = verifier.push(c);
+            if i == 2 {
+                assert_eq!(verifier.push('-'), None);
+            }
+            if i < 5 {
+                assert_eq!(result, None);
+            } else {
+                assert_eq!(result, Some(true));
+            }
+        }
+        assert_eq!(verifier.push('1'), Some(true));
+
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        let mut verifier = StreamingVerifier::new_at(&authenticator, 0, timestamp);
+        assert_eq!(
+            wrong.chars().filter_map(|c| verifier.push(c)).last(),
+            Some(false)
+        );
+
+        let mut verifier = StreamingVerifier::new_at(&authenticator, 1, timestamp + 90);
+        assert_eq!(
+            code.chars().filter_map(|c| verifier.push(c)).last(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn with_algorithm() {
+        let authenticator = Authenticator::new(RFC6238_SHA1_SECRET, 8);
+        let sha256 = authenticator.with_algorithm(Algorithm::SHA256);
+        let (timestamp, sha1_code, _, _) = RFC6238_VECTORS[0];
+        assert_eq!(sha256.algorithm(), Algorithm::SHA256);
+        assert_eq!(sha256.secret_bytes(), authenticator.secret_bytes());
+        assert_eq!(authenticator.generate_totp_string_at(timestamp), sha1_code);
+        assert_ne!(
+            sha256.generate_totp_at(timestamp),
+            authenticator.generate_totp_at(timestamp)
+        );
+        assert_eq!(sha256.with_algorithm(Algorithm::SHA1), authenticator);
+    }
+
+    #[test]
+    fn verify_totp_logged() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = 1_111_111_110;
+        let step = timestamp / 30 - 1;
+        let code = authenticator.generate_hotp(step);
+        assert_eq!(
+            authenticator.verify_totp_logged_at(code, 1, timestamp),
+            Some(step)
+        );
+        assert_eq!(
+            authenticator.verify_totp_logged_at(code, 0, timestamp),
+            None
+        );
+        assert_eq!(
+            authenticator
+                .verify_context()
+                .verify_detailed(code, 1, timestamp),
+            VerifyResult::Valid {
+                step_offset: -1,
+                step
+            }
+        );
+    }
+
+    #[test]
+    fn verify_totp_buf() {
+        let authenticator = Authenticator::new(RFC4226_SECRET, 6);
+        let mut buf = [0u8; 8];
+        assert_eq!(authenticator.write_hotp_code(0, &mut buf), Ok(6));
+        assert_eq!(&buf[..6], RFC4226_VECTORS[0].0.as_bytes());
+
+        let timestamp = 1_111_111_110;
+        for code in &[
+            authenticator.generate_totp_string_at(timestamp),
+            authenticator.generate_totp_string_at(timestamp + 60),
+            "123456".to_string(),
+            "12345".to_string(),
+        ] {
+            assert_eq!(
+                authenticator.verify_totp_buf_at(code.as_bytes(), 1, timestamp, &mut buf),
+                Ok(authenticator.verify_totp_str_at(code, 1, timestamp))
+            );
+        }
+
+        assert!(authenticator
+            .verify_totp_buf_at(b"123456", 1, timestamp, &mut [0u8; 5])
+            .is_err());
+    }
+
+    #[test]
+    fn counter_direction_down() {
+        let mut authenticator =
+            Authenticator::new(RFC4226_SECRET, 6).with_direction(CounterDirection::Down);
+        authenticator.counter = 9;
+        let code = authenticator.generate_hotp(7);
+        assert!(authenticator.verify_and_advance(code));
+        assert_eq!(authenticator.counter(), 6);
+        assert_eq!(authenticator.direction(), CounterDirection::Down);
+        assert!(!authenticator.verify_and_advance(code));
+
+        let mut up = Authenticator::new(RFC4226_SECRET, 6);
+        up.counter = 8;
+        assert!(!up.verify_and_advance(code));
+
+        let typed = authenticator.generate_hotp_string(4);
+        assert_eq!(authenticator.verify_hotp_str(6, &typed, 3), Some(4));
+        assert_eq!(up.verify_hotp_str(6, &typed, 3), None);
+    }
+
+    #[test]
+    fn counter_direction_down_exhausted() {
+        let mut authenticator =
+            Authenticator::new(RFC4226_SECRET, 6).with_direction(CounterDirection::Down);
+        authenticator.counter = 1;
+        let last = authenticator.generate_hotp(0);
+        for _ in 0..3 {
+            assert!(!authenticator.verify_and_advance(last));
+            assert_eq!(authenticator.counter(), 1);
+        }
+
+        assert!(authenticator.verify_and_advance(authenticator.generate_hotp(1)));
+        assert_eq!(authenticator.counter(), 0);
+        assert!(!authenticator.verify_and_advance(last));
+        assert_eq!(authenticator.counter(), 0);
+    }
+
+    #[test]
+    fn authenticator_set_merge() {
+        let existing = {
+            let mut set = AuthenticatorSet::new();
+            set.insert("alice", Authenticator::new("alicesecret", 6));
+            set.insert("bob", Authenticator::new("bobsecret", 6));
+            set
+        };
+        let incoming = {
+            let mut set = AuthenticatorSet::new();
+            set.insert("alice", Authenticator::new("restoredsecret", 6));
+            set.insert("carol", Authenticator::new("carolsecret", 6));
+            set
+        };
+
+        let mut kept = existing.clone();
+        kept.merge(incoming.clone(), MergeStrategy::KeepExisting);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(kept.get("alice").unwrap().secret_bytes(), b"alicesecret");
+
+        let mut preferred = existing.clone();
+        preferred.merge(incoming.clone(), MergeStrategy::PreferIncoming);
+        assert_eq!(preferred.len(), 3);
+        assert_eq!(
+            preferred.get("alice").unwrap().secret_bytes(),
+            b"restoredsecret"
+        );
+
+        let mut both = existing;
+        both.insert("alice (2)", Authenticator::new("oldsecret", 6));
+        both.merge(incoming, MergeStrategy::KeepBoth);
+        assert_eq!(both.len(), 5);
+        assert_eq!(both.get("alice").unwrap().secret_bytes(), b"alicesecret");
+        assert_eq!(
+            both.get("alice (3)").unwrap().secret_bytes(),
+            b"restoredsecret"
+        );
+    }
+
+    #[test]
+    fn authenticator_set_merge_hotp_counter() {
+        let token_url = "otpauth://hotp/alice?secret=MZQWWZLTMVRXEZLU";
+        let hotp = |counter: u64| {
+            let mut authenticator = Authenticator::from_token_url(token_url).unwrap();
+            authenticator.counter = counter;
+            authenticator
+        };
+
+        for strategy in &[
+            MergeStrategy::KeepExisting,
+            MergeStrategy::PreferIncoming,
+            MergeStrategy::KeepBoth,
+        ] {
+            let mut set = AuthenticatorSet::new();
+            set.insert("alice", hotp(5));
+            let mut backup = AuthenticatorSet::new();
+            backup.insert("alice", hotp(20));
+            set.merge(backup, *strategy);
+            for (_, authenticator) in &set {
+                assert_eq!(authenticator.counter(), 20);
+            }
+
+            let mut backup = AuthenticatorSet::new();
+            backup.insert("alice", hotp(3));
+            set.merge(backup, *strategy);
+            for (_, authenticator) in &set {
+                assert_eq!(authenticator.counter(), 20);
+            }
+
+            let down = |counter: u64| hotp(counter).with_direction(CounterDirection::Down);
+            let mut set = AuthenticatorSet::new();
+            set.insert("alice", down(2));
+            let mut backup = AuthenticatorSet::new();
+            backup.insert("alice", down(5));
+            set.merge(backup, *strategy);
+            for (_, authenticator) in &set {
+                assert_eq!(authenticator.counter(), 2);
+            }
+            let used = set.get("alice").unwrap().generate_hotp(4);
+            assert!(!set.get_mut("alice").unwrap().verify_and_advance(used));
+
+            let mut set = AuthenticatorSet::new();
+            set.insert("alice", down(2));
+            let mut backup = AuthenticatorSet::new();
+            backup.insert("alice", hotp(5));
+            set.merge(backup, *strategy);
+            for (_, authenticator) in &set {
+                let expected = match authenticator.direction() {
+                    CounterDirection::Up => 5,
+                    CounterDirection::Down => 2,
+                };
+                assert_eq!(authenticator.counter(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn to_migration_url() {
+        let mut set = AuthenticatorSet::new();
+        set.insert("alice", Authenticator::new("fakesecret", 6));
+        let url = set.to_migration_url().unwrap();
+        assert!(url.starts_with("otpauth-migration://offline?data="));
+
+        let data = url.trim_start_matches("otpauth-migration://offline?data=");
+        let payload =
+            base64::decode(percent_decode_str(data).decode_utf8().unwrap().as_ref()).unwrap();
+        assert_eq!(&payload[..4], &[0x0a, 25, 0x0a, 10]);
+        assert_eq!(&payload[4..14], b"fakesecret");
+        assert_eq!(&payload[payload.len() - 4..], &[0x10, 1, 0x18, 1]);
+
+        set.insert("bob", Authenticator::new("fakesecret", 7));
+        match set.to_migration_url() {
+            Err(Error::InvalidDigits(message)) => {
+                assert!(message.contains("\"bob\""));
+                assert!(message.contains("7 digits"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut set = AuthenticatorSet::new();
+        let hotp = Authenticator::from_token_url("otpauth://hotp/carol?secret=MZQWWZLTMVRXEZLU")
+            .unwrap()
+            .with_period(60)
+            .unwrap();
+        set.insert("carol", hotp.clone());
+        assert!(set.to_migration_url().is_ok());
+
+        let mut totp = hotp;
+        totp.otp_type = OtpType::Totp;
+        set.insert("carol", totp);
+        assert!(matches!(
+            set.to_migration_url(),
+            Err(Error::InvalidPeriod(_))
+        ));
+    }
+
+    #[test]
+    fn generate_secret_base32() {
+        let encoded = super::generate_secret_base32(20);
+        assert_eq!(encoded.len(), 32);
+        let authenticator = Authenticator::from_base32(&encoded, 6).unwrap();
+        assert_eq!(authenticator.secret_bytes().len(), 20);
+        assert_ne!(encoded, super::generate_secret_base32(20));
+
+        let fixed =
+            super::generate_secret_base32_with(10, |buf| buf.copy_from_slice(b"fakesecret"));
+        assert_eq!(fixed, "MZQWWZLTMVRXEZLU");
+    }
+
+    #[test]
+    fn verify_totp_normalized() {
+        let authenticator = Authenticator::new("fakesecret", 6);
+        let timestamp = (1_111_111_110..)
+            .step_by(30)
+            .find(|&ts| authenticator.generate_totp_string_at(ts).starts_with('0'))
+            .unwrap();
+        let code = authenticator.generate_totp_string_at(timestamp);
+        let typed = format!("{} {}", &code[1..3], &code[3..]);
+
+        assert_eq!(
+            authenticator.verify_totp_normalized_at(&typed, 0, timestamp),
+            Some(code.clone())
+        );
+        assert_eq!(
+            authenticator.verify_totp_normalized_at(&code, 0, timestamp),
+            Some(code)
+        );
+        assert_eq!(
+            authenticator.verify_totp_normalized_at(&typed, 0, timestamp + 30),
+            None
+        );
+        assert_eq!(
+            authenticator.verify_totp_normalized_at("12a45", 0, timestamp),
+            None
+        );
+        assert_eq!(
+            authenticator.verify_totp_normalized_at(" ", 0, timestamp),
+            None
+        );
+    }
+
+    #[test]
+    fn time_step() {
+        assert_eq!(super::time_step(59, 30, 0), 1);
+        assert_eq!(super::time_step(59, 30, 30), 0);
+        assert_eq!(super::time_step(10, 30, 60), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn time_step_zero_period() {
+        super::time_step(10, 0, 0);
     }
 }